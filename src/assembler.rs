@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::Instruction;
+use crate::InstructionReceiver;
+
+/// Assembles mnemonic source into bytecode
+///
+/// Each line holds at most one of the following, and anything after a `;` is a comment:\
+/// `name:` defines a label at the address of the following instruction\
+/// `MNEMONIC` emits an instruction without an operand, such as `ADD_F_8`\
+/// `PSH_N operand` emits a push, where the operand is a decimal or `0x` prefixed hex integer,
+/// a float literal (only for `PSH_4` and `PSH_8`), or a label name (only for `PSH_8`)\
+/// `JMP_IMM operand` emits an immediate jump, where the operand is an integer or a label name
+///
+/// labels resolve to the 8 byte addresses that `JSR`, `RET` and the `JMP_*` instructions expect
+pub fn assemble(src: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut labels = HashMap::new();
+    let mut lines = Vec::new();
+    let mut address = 0;
+    for (index, line) in src.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.split(';').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(label) = line.strip_suffix(':') {
+            let label = label.trim();
+            if labels.insert(label, address as u64).is_some() {
+                return Err(AssembleError::DuplicateLabel(line_number, label.to_string()));
+            }
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let mnemonic = parts.next().unwrap();
+        let operand = parts.next();
+        if parts.next().is_some() {
+            return Err(AssembleError::UnexpectedOperand(line_number));
+        }
+        let instruction = Instruction::from_mnemonic(mnemonic)
+            .ok_or_else(|| AssembleError::UnknownMnemonic(line_number, mnemonic.to_string()))?;
+        address += instruction.encoded_len();
+        lines.push((line_number, instruction, operand));
+    }
+
+    let mut bytecode = Vec::with_capacity(address);
+    for (line_number, instruction, operand) in lines {
+        let instruction = match (instruction, operand) {
+            (Instruction::PSH_1(_), Some(operand)) => Instruction::PSH_1(
+                parse_integer(operand).ok_or_else(|| AssembleError::InvalidOperand(line_number, operand.to_string()))?
+            ),
+            (Instruction::PSH_2(_), Some(operand)) => Instruction::PSH_2(
+                parse_integer(operand).ok_or_else(|| AssembleError::InvalidOperand(line_number, operand.to_string()))?
+            ),
+            (Instruction::PSH_4(_), Some(operand)) => Instruction::PSH_4(
+                parse_integer(operand)
+                    .or_else(|| parse_float(operand).map(|v: f32| v.to_le_bytes()))
+                    .ok_or_else(|| AssembleError::InvalidOperand(line_number, operand.to_string()))?
+            ),
+            (Instruction::PSH_8(_), Some(operand)) => Instruction::PSH_8(
+                parse_integer(operand)
+                    .or_else(|| parse_float(operand).map(|v: f64| v.to_le_bytes()))
+                    .or_else(|| labels.get(operand).map(|address| address.to_le_bytes()))
+                    .ok_or_else(|| if is_label_name(operand) {
+                        AssembleError::UndefinedLabel(line_number, operand.to_string())
+                    } else {
+                        AssembleError::InvalidOperand(line_number, operand.to_string())
+                    })?
+            ),
+            (Instruction::JMP_IMM(_), Some(operand)) => Instruction::JMP_IMM(
+                parse_integer(operand)
+                    .or_else(|| labels.get(operand).map(|address| address.to_le_bytes()))
+                    .ok_or_else(|| if is_label_name(operand) {
+                        AssembleError::UndefinedLabel(line_number, operand.to_string())
+                    } else {
+                        AssembleError::InvalidOperand(line_number, operand.to_string())
+                    })?
+            ),
+            (
+                Instruction::PSH_1(_) | Instruction::PSH_2(_) | Instruction::PSH_4(_) | Instruction::PSH_8(_) |
+                Instruction::JMP_IMM(_),
+                None
+            ) => {
+                return Err(AssembleError::MissingOperand(line_number));
+            }
+            (_, Some(_)) => return Err(AssembleError::UnexpectedOperand(line_number)),
+            (instruction, None) => instruction
+        };
+        bytecode.instruct(instruction);
+    }
+    Ok(bytecode)
+}
+
+/// Parses a decimal or `0x` prefixed hex integer, with at most one leading `-` or `+`, which fits
+/// in `N` bytes as either a signed or an unsigned value
+fn parse_integer<const N: usize>(operand: &str) -> Option<[u8; N]> {
+    let (negative, digits) = match operand.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, operand.strip_prefix('+').unwrap_or(operand))
+    };
+    let (digits, radix) = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => (hex, 16),
+        None => (digits, 10)
+    };
+    // the sign was already taken, and parsing would accept a second one
+    if digits.starts_with(['+', '-']) {
+        return None;
+    }
+    let magnitude = i128::from_str_radix(digits, radix).ok()?;
+    let value = if negative { magnitude.checked_neg()? } else { magnitude };
+    let bits = N as u32 * 8;
+    let min = -(1i128 << (bits - 1));
+    let max = (1i128 << bits) - 1;
+    if value < min || value > max {
+        return None;
+    }
+    Some(value.to_le_bytes()[..N].try_into().unwrap())
+}
+
+fn parse_float<T: std::str::FromStr>(operand: &str) -> Option<T> {
+    operand.parse().ok()
+}
+
+fn is_label_name(operand: &str) -> bool {
+    operand.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+}
+
+#[derive(Debug)]
+pub enum AssembleError {
+    /// The mnemonic on the given line does not name an instruction
+    UnknownMnemonic(usize, String),
+    /// A push or immediate jump on the given line has no operand
+    MissingOperand(usize),
+    /// The given line has an operand where none is accepted
+    UnexpectedOperand(usize),
+    /// The operand on the given line could not be parsed, or does not fit the push or address width
+    InvalidOperand(usize, String),
+    /// The label used on the given line is never defined
+    UndefinedLabel(usize, String),
+    /// The label defined on the given line was already defined
+    DuplicateLabel(usize, String)
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AssembleError::UnknownMnemonic(line, mnemonic) => write!(f, "line {}: unknown mnemonic {}", line, mnemonic),
+            AssembleError::MissingOperand(line) => write!(f, "line {}: push is missing its operand", line),
+            AssembleError::UnexpectedOperand(line) => write!(f, "line {}: unexpected operand", line),
+            AssembleError::InvalidOperand(line, operand) => write!(f, "line {}: invalid operand {}", line, operand),
+            AssembleError::UndefinedLabel(line, label) => write!(f, "line {}: label {} is never defined", line, label),
+            AssembleError::DuplicateLabel(line, label) => write!(f, "line {}: label {} is already defined", line, label)
+        }
+    }
+}
+
+impl Error for AssembleError {}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::machine::Machine;
+
+    #[test]
+    fn assembles_and_runs_a_loop() {
+        let program = assemble("
+            PSH_8 0         ; total
+            PSH_8 3         ; counter
+        loop:
+            SWAP_8
+            PSH_8 2
+            ADD_8           ; total += 2
+            SWAP_8
+            PSH_8 1
+            SWAP_8
+            SUB_8           ; counter -= 1
+            DUP_8
+            PSH_8 loop
+            SWAP_8
+            PSH_8 0
+            SWAP_8
+            CMP_U_8
+            JMP_NE          ; loop while the counter is not 0
+        ").unwrap();
+        let mut machine = Machine::new(Box::new(Cursor::new(program)));
+        machine.run().unwrap();
+        assert_eq!(machine.stack.pop_u64().unwrap(), 0);
+        assert_eq!(machine.stack.pop_u64().unwrap(), 6);
+        assert_eq!(machine.stack.size(), 0);
+    }
+
+    #[test]
+    fn labels_resolve_to_addresses() {
+        let program = assemble("
+            PSH_8 end
+            RET
+            PSH_8 start
+        start:
+            PSH_2 0x1234
+        end:
+            POP_8
+        ").unwrap();
+        let push_address_len = Instruction::PSH_8([0; 8]).encoded_len();
+        let start = push_address_len + Instruction::RET.encoded_len() + push_address_len;
+        let end = start + Instruction::PSH_2([0; 2]).encoded_len();
+        let mut expected = Vec::new();
+        expected
+            .instruct(Instruction::PSH_8((end as u64).to_le_bytes()))
+            .instruct(Instruction::RET)
+            .instruct(Instruction::PSH_8((start as u64).to_le_bytes()))
+            .instruct(Instruction::PSH_2(0x1234u16.to_le_bytes()))
+            .instruct(Instruction::POP_8);
+        assert_eq!(program, expected);
+    }
+
+    #[test]
+    fn parses_immediates() {
+        let mut expected = Vec::new();
+        expected
+            .instruct(Instruction::PSH_1([0xFF]))
+            .instruct(Instruction::PSH_1([0xFF]))
+            .instruct(Instruction::PSH_1([5]))
+            .instruct(Instruction::PSH_2((-0x10i16).to_le_bytes()))
+            .instruct(Instruction::PSH_4(1.5f32.to_le_bytes()))
+            .instruct(Instruction::PSH_8(i64::MIN.to_le_bytes()));
+        let program = assemble("PSH_1 255\npsh_1 -1\nPSH_1 +5\nPSH_2 -0x10\nPSH_4 1.5\nPSH_8 -9223372036854775808");
+        assert_eq!(program.unwrap(), expected);
+    }
+
+    #[test]
+    fn reports_the_offending_line() {
+        assert!(matches!(assemble("PSH_1 256"), Err(AssembleError::InvalidOperand(1, _))));
+        assert!(matches!(assemble("PSH_1 1.5"), Err(AssembleError::InvalidOperand(1, _))));
+        assert!(matches!(assemble("PSH_1"), Err(AssembleError::MissingOperand(1))));
+        assert!(matches!(assemble("ADD_1 5"), Err(AssembleError::UnexpectedOperand(1))));
+        assert!(matches!(assemble("PSH_1 1 2"), Err(AssembleError::UnexpectedOperand(1))));
+        assert!(matches!(assemble("FOO"), Err(AssembleError::UnknownMnemonic(1, _))));
+        assert!(matches!(assemble("\nPSH_8 nowhere"), Err(AssembleError::UndefinedLabel(2, _))));
+        assert!(matches!(assemble("a:\na:"), Err(AssembleError::DuplicateLabel(2, _))));
+    }
+
+    #[test]
+    fn rejects_doubled_signs() {
+        for operand in [
+            "--5", "+-5", "-+5", "++5", "0x-5", "-0x+5",
+            "--170141183460469231731687303715884105728", "-170141183460469231731687303715884105729"
+        ] {
+            let result = assemble(&format!("PSH_1 {}", operand));
+            assert!(matches!(result, Err(AssembleError::InvalidOperand(1, _))), "{}: {:?}", operand, result);
+        }
+        assert!(matches!(assemble("PSH_8 --1.5"), Err(AssembleError::InvalidOperand(1, _))));
+    }
+
+    #[test]
+    fn errors_display_their_line() {
+        assert_eq!(assemble("\n\nFOO").unwrap_err().to_string(), "line 3: unknown mnemonic FOO");
+        assert_eq!(assemble("PSH_8 nowhere").unwrap_err().to_string(), "line 1: label nowhere is never defined");
+        let error: Box<dyn Error> = Box::new(assemble("PSH_1").unwrap_err());
+        assert_eq!(error.to_string(), "line 1: push is missing its operand");
+    }
+
+    #[test]
+    fn assembles_immediate_jumps() {
+        let program = assemble("
+            JMP_IMM end
+            PSH_1 2
+        end:
+            JMP_IMM 0x10
+        ").unwrap();
+        let mut expected = Vec::new();
+        expected
+            .instruct(Instruction::JMP_IMM(11u64.to_le_bytes()))
+            .instruct(Instruction::PSH_1([2]))
+            .instruct(Instruction::JMP_IMM(16u64.to_le_bytes()));
+        assert_eq!(program, expected);
+        assert!(matches!(assemble("JMP_IMM"), Err(AssembleError::MissingOperand(1))));
+        assert!(matches!(assemble("JMP_IMM nowhere"), Err(AssembleError::UndefinedLabel(1, _))));
+    }
+
+    #[test]
+    fn assembles_psh_null_without_an_operand() {
+        assert_eq!(assemble("PSH_NULL").unwrap(), vec![Instruction::PSH_NULL.opcode()]);
+    }
+}
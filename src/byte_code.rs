@@ -0,0 +1,171 @@
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+/// Read only bytecode which can be shared between machines without copying it, each clone
+/// reading from its own position
+#[derive(Clone, Debug)]
+pub struct ByteCode(Cursor<Arc<[u8]>>);
+
+impl ByteCode {
+    pub fn new(program: Arc<[u8]>) -> Self {
+        Self(Cursor::new(program))
+    }
+
+    /// The shared bytes of the program
+    pub fn program(&self) -> &Arc<[u8]> {
+        self.0.get_ref()
+    }
+}
+
+impl From<Vec<u8>> for ByteCode {
+    fn from(program: Vec<u8>) -> Self {
+        Self::new(program.into())
+    }
+}
+
+impl Read for ByteCode {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.0.read_exact(buf)
+    }
+}
+
+impl Seek for ByteCode {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        self.0.stream_position()
+    }
+}
+
+/// Adapts a source which can only be read forwards, such as a pipe or socket, into an instruction
+/// source by keeping every byte read from it
+///
+/// seeking is possible to any position which has already been read, which covers backward jumps
+/// into code that has already been executed, seeking any further, or from the end, fails with
+/// an [io::Error]
+#[derive(Debug)]
+pub struct BufferedProgram<R: Read> {
+    source: R,
+    buffer: Vec<u8>,
+    position: usize
+}
+
+impl<R: Read> BufferedProgram<R> {
+    pub fn new(source: R) -> Self {
+        Self {
+            source,
+            buffer: Vec::new(),
+            position: 0
+        }
+    }
+
+    /// The bytes read from the source so far
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl<R: Read> Read for BufferedProgram<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = if self.position < self.buffer.len() {
+            let buffered = &self.buffer[self.position..];
+            let n = buffered.len().min(buf.len());
+            buf[..n].copy_from_slice(&buffered[..n]);
+            n
+        } else {
+            let n = self.source.read(buf)?;
+            self.buffer.extend_from_slice(&buf[..n]);
+            n
+        };
+        self.position += n;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Seek for BufferedProgram<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(n) => usize::try_from(n).ok(),
+            SeekFrom::Current(offset) => i64::try_from(self.position).ok()
+                .and_then(|position| position.checked_add(offset))
+                .and_then(|position| usize::try_from(position).ok()),
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "the end of a buffered program is not known"));
+            }
+        };
+        match position {
+            Some(position) if position <= self.buffer.len() => {
+                self.position = position;
+                Ok(position as u64)
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot seek beyond the bytes read so far"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::machine::Machine;
+
+    #[test]
+    fn clones_read_independently() {
+        let mut a = ByteCode::from(vec![1, 2, 3, 4]);
+        let mut b = a.clone();
+        let mut buf = [0; 2];
+        a.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2]);
+        b.seek(SeekFrom::Start(3)).unwrap();
+        b.read_exact(&mut buf[..1]).unwrap();
+        assert_eq!(buf[0], 4);
+        assert_eq!(a.stream_position().unwrap(), 2);
+        assert!(Arc::ptr_eq(a.program(), b.program()));
+    }
+
+    #[test]
+    fn buffered_programs_jump_backwards() {
+        let program = assemble("
+            PSH_8 3         ; counter
+        loop:
+            PSH_8 1
+            SWAP_8
+            SUB_8           ; counter -= 1
+            DUP_8
+            PSH_8 loop
+            SWAP_8
+            PSH_8 0
+            SWAP_8
+            CMP_U_8
+            JMP_NE          ; loop while the counter is not 0
+        ").unwrap();
+        // a VecDeque can only be read forwards
+        let source: VecDeque<u8> = program.into();
+        let mut machine = Machine::new(Box::new(BufferedProgram::new(source)));
+        machine.run().unwrap();
+        assert_eq!(machine.stack().size(), 8);
+        assert_eq!(machine.steps_executed(), 1 + 3 * 10);
+    }
+
+    #[test]
+    fn buffered_programs_only_seek_within_what_was_read() {
+        let mut program = BufferedProgram::new(VecDeque::from(vec![1, 2, 3, 4]));
+        let mut buf = [0; 2];
+        program.read_exact(&mut buf).unwrap();
+        assert!(program.seek(SeekFrom::Start(3)).is_err());
+        assert!(program.seek(SeekFrom::End(0)).is_err());
+        assert_eq!(program.seek(SeekFrom::Current(-2)).unwrap(), 0);
+        program.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2]);
+        assert_eq!(program.buffered(), &[1, 2]);
+    }
+}
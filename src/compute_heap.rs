@@ -1,63 +1,255 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
 use std::num::NonZeroU64;
 
+/// Cloning a heap copies the data and children of every object, so the clone can be mutated
+/// independently, such as for snapshots or speculative execution
+#[derive(Clone)]
 pub struct Heap {
-    // this implementation makes no attempt to reclaim old allocation indices,
-    // and simply increments the counter until it overflows, then returns a HeapError::Allocation
-    // for any following allocation attempts
-    counter: NonZeroU64,
-    reference_map: HashMap<ObjectReference, Object>
+    /// objects by the slot index packed into their [ObjectReference]
+    slots: Vec<Slot>,
+    // empty slots are reused before new ones are added, the slots only run out (returning a
+    // HeapError::Allocation) once u32::MAX - 1 objects are alive at once
+    free_slots: Vec<u32>,
+    object_count: usize,
+    /// objects kept alive by the host as if they had stack references, see [Heap::pin]
+    pinned: HashSet<ObjectReference>,
+    /// the running total of [Object::allocated_bytes] over all objects
+    allocated_bytes: usize,
+    max_bytes: usize,
+    /// whether objects are removed as soon as their last stack reference is, see
+    /// [Heap::set_auto_collect]
+    auto_collect: bool,
+    /// the collection started by [Heap::collect_garbage_incremental], if it has not finished yet
+    collection: Option<Collection>,
+    /// the sum of the stats of every finished collection
+    gc_totals: GcStats
 }
 
 impl Heap {
     pub fn new() -> Self {
+        Self::with_limit(usize::MAX)
+    }
+
+    /// Creates a heap which fails allocations with [HeapError::OutOfMemory] once the objects on it
+    /// would hold more than `max_bytes` bytes, as counted by [Heap::total_allocated_bytes]
+    pub fn with_limit(max_bytes: usize) -> Self {
         Self {
-            counter: NonZeroU64::new(1).unwrap(),
-            reference_map: HashMap::new()
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+            object_count: 0,
+            pinned: HashSet::new(),
+            allocated_bytes: 0,
+            max_bytes,
+            auto_collect: false,
+            collection: None,
+            gc_totals: GcStats::default()
         }
     }
 
+    /// When enabled, [Heap::decrement_stack_references] removes an object right away once its last
+    /// stack reference is removed and no object holds it as a child, as [Heap::free] does
+    ///
+    /// objects only held as children by a removed object are left for [Heap::collect_garbage]
+    pub fn set_auto_collect(&mut self, auto_collect: bool) {
+        self.auto_collect = auto_collect;
+    }
+
+    /// Allocates an object, failing with [HeapError::Allocation] if the object would hold more than
+    /// `isize::MAX` bytes, which no allocation can satisfy, or if its memory can not be reserved
     pub fn allocate(&mut self, children_length: usize, data_length: usize) -> HeapResult<ObjectReference> {
-        let obj = Object::new(children_length, data_length);
-        let obj_ref = ObjectReference(self.get_and_increment_counter()?);
-        self.reference_map.insert(obj_ref.clone(), obj);
+        let obj_bytes = children_length.checked_mul(8)
+            .and_then(|children_bytes| children_bytes.checked_add(data_length))
+            .filter(|obj_bytes| *obj_bytes <= isize::MAX as usize)
+            .ok_or(HeapError::Allocation)?;
+        let allocated_bytes = obj_bytes.checked_add(self.allocated_bytes)
+            .filter(|allocated_bytes| *allocated_bytes <= self.max_bytes)
+            .ok_or(HeapError::OutOfMemory)?;
+        let obj = Object::new(children_length, data_length)?;
+        let index = match self.free_slots.pop() {
+            Some(index) => index,
+            None => {
+                // the last index is left unused, as the reference holds the index plus one
+                let index = u32::try_from(self.slots.len()).ok()
+                    .filter(|index| *index < u32::MAX)
+                    .ok_or(HeapError::Allocation)?;
+                self.slots.push(Slot { generation: 0, object: None });
+                index
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.object = Some(obj);
+        self.object_count += 1;
+        self.allocated_bytes = allocated_bytes;
+        let obj_ref = ObjectReference::from_slot(index, slot.generation);
+        if let Some(collection) = &mut self.collection {
+            // objects allocated during a collection are kept until the next one
+            collection.reachable.resize(self.slots.len(), false);
+            collection.reachable[index as usize] = true;
+        }
         Ok(obj_ref)
     }
 
     pub fn increment_stack_references(&mut self, obj_ref: &ObjectReference) -> HeapResult<ObjectReference> {
-        let obj = self.get_mut_object(&obj_ref)?;
+        let obj = self.get_mut_object(obj_ref)?;
         obj.stack_references = obj.stack_references.checked_add(1).ok_or(HeapError::StackReferenceError)?;
+        self.mark_reachable(obj_ref);
         Ok(obj_ref.clone())
     }
 
     pub fn decrement_stack_references(&mut self, obj_ref: ObjectReference) -> HeapResult<()> {
-        let obj = self.get_mut_object(&obj_ref)?;
+        let stack_references = self.decrement(&obj_ref)?;
+        if self.auto_collect && stack_references == 0 {
+            self.remove_if_unreferenced(&obj_ref);
+        }
+        Ok(())
+    }
+
+    /// Removes a stack reference to the object, returning how many remain
+    fn decrement(&mut self, obj_ref: &ObjectReference) -> HeapResult<u64> {
+        let obj = self.get_mut_object(obj_ref)?;
         obj.stack_references = obj.stack_references.checked_sub(1).ok_or(HeapError::StackReferenceError)?;
+        Ok(obj.stack_references)
+    }
+
+    /// Keeps an object, and the objects it refers to, alive through collections without it
+    /// needing a stack reference, until [Heap::unpin] is called
+    ///
+    /// pinning an object more than once has no further effect
+    pub fn pin(&mut self, obj_ref: &ObjectReference) -> HeapResult<()> {
+        self.get_object(obj_ref)?;
+        self.pinned.insert(obj_ref.clone());
+        self.mark_reachable(obj_ref);
         Ok(())
     }
 
+    /// Lets a pinned object be collected again once nothing else keeps it alive, returning whether
+    /// it was pinned
+    pub fn unpin(&mut self, obj_ref: &ObjectReference) -> bool {
+        self.pinned.remove(obj_ref)
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.object_count
+    }
+
+    /// References to every object on the heap, in no particular order
+    pub fn live_references(&self) -> impl Iterator<Item = ObjectReference> + '_ {
+        self.objects().map(|(obj_ref, _)| obj_ref)
+    }
+
+    fn objects(&self) -> impl Iterator<Item = (ObjectReference, &Object)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let obj = slot.object.as_ref()?;
+            Some((ObjectReference::from_slot(index as u32, slot.generation), obj))
+        })
+    }
+
+    /// The bytes held by all objects, counting each child slot as 8 bytes
+    pub fn total_allocated_bytes(&self) -> usize {
+        self.allocated_bytes
+    }
+
+    /// Removes a stack reference to the object, then immediately removes the object itself if that
+    /// was its last stack reference and no object refers to it as a child, returning whether it
+    /// was removed
+    pub fn free(&mut self, obj_ref: ObjectReference) -> HeapResult<bool> {
+        if self.decrement(&obj_ref)? > 0 {
+            return Ok(false);
+        }
+        Ok(self.remove_if_unreferenced(&obj_ref))
+    }
+
+    /// Removes an object without stack references if it is not pinned and no object holds it as
+    /// a child, returning whether it was removed
+    fn remove_if_unreferenced(&mut self, obj_ref: &ObjectReference) -> bool {
+        let referenced = self.pinned.contains(obj_ref)
+            || self.get_object(obj_ref).map_or(true, |obj| obj.parent_references > 0);
+        if referenced {
+            return false;
+        }
+        self.remove_object(obj_ref);
+        true
+    }
+
+    fn remove_object(&mut self, obj_ref: &ObjectReference) {
+        let (index, generation) = obj_ref.slot();
+        let Some(slot) = self.slots.get_mut(index).filter(|slot| slot.generation == generation) else {
+            return;
+        };
+        let Some(obj) = slot.object.take() else {
+            return;
+        };
+        self.allocated_bytes -= obj.allocated_bytes();
+        self.object_count -= 1;
+        // a slot whose generations ran out is never reused, as a new object in it could be
+        // reached through references to an old one
+        if let Some(generation) = slot.generation.checked_add(1) {
+            slot.generation = generation;
+            self.free_slots.push(index as u32);
+        }
+        for child in obj.children.iter().flatten() {
+            self.remove_parent_reference(child);
+        }
+    }
+
+    fn remove_parent_reference(&mut self, child: &ObjectReference) {
+        // the child may have been removed already, such as by the same collection
+        if let Ok(child) = self.get_mut_object(child) {
+            child.parent_references -= 1;
+        }
+    }
+
     fn get_object(&self, obj_ref: &ObjectReference) -> HeapResult<&Object> {
-        self.reference_map.get(obj_ref).ok_or(HeapError::ObjectNotFound)
+        let (index, generation) = obj_ref.slot();
+        self.slots.get(index)
+            .filter(|slot| slot.generation == generation)
+            .and_then(|slot| slot.object.as_ref())
+            .ok_or(HeapError::ObjectNotFound)
     }
 
     fn get_mut_object(&mut self, obj_ref: &ObjectReference) -> HeapResult<&mut Object> {
-        self.reference_map.get_mut(obj_ref).ok_or(HeapError::ObjectNotFound)
+        let (index, generation) = obj_ref.slot();
+        self.slots.get_mut(index)
+            .filter(|slot| slot.generation == generation)
+            .and_then(|slot| slot.object.as_mut())
+            .ok_or(HeapError::ObjectNotFound)
     }
 
-    fn get_and_increment_counter(&mut self) -> HeapResult<NonZeroU64> {
-        let n = self.counter;
-        self.counter = NonZeroU64::new(self.counter.get() + 1).ok_or(HeapError::Allocation)?;
-        Ok(n)
+    pub fn set_child(&mut self, parent: &ObjectReference, index: usize, child: Option<&ObjectReference>) -> HeapResult<()> {
+        let previous = self.get_object(parent)?.get_child(index)?;
+        if let Some(child) = child {
+            self.get_mut_object(child)?.parent_references += 1;
+        }
+        self.get_mut_object(parent)?.set_child(index, child)?;
+        if let Some(previous) = previous {
+            self.remove_parent_reference(&previous);
+        }
+        if let Some(child) = child {
+            self.mark_reachable(child);
+        }
+        Ok(())
     }
 
-    pub fn set_child(&mut self, parent: &ObjectReference, index: usize, child: Option<&ObjectReference>) -> HeapResult<()> {
-        self.get_mut_object(parent)?.set_child(index, child)
+    /// Whether `obj_ref` refers to an object on this heap
+    pub fn contains(&self, obj_ref: &ObjectReference) -> bool {
+        self.get_object(obj_ref).is_ok()
     }
 
     pub fn get_child(&self, parent: &ObjectReference, index: usize) -> HeapResult<Option<ObjectReference>> {
         self.get_object(parent)?.get_child(index)
     }
 
+    pub fn children_len(&self, obj_ref: &ObjectReference) -> HeapResult<usize> {
+        Ok(self.get_object(obj_ref)?.children.len())
+    }
+
+    pub fn data_len(&self, obj_ref: &ObjectReference) -> HeapResult<usize> {
+        Ok(self.get_object(obj_ref)?.data.len())
+    }
+
     pub fn get_data_slice(&self, obj_ref: &ObjectReference, start: usize, length: usize) -> HeapResult<&[u8]> {
         self.get_object(obj_ref)?.get_data_slice(start, length)
     }
@@ -66,49 +258,189 @@ impl Heap {
         self.get_mut_object(obj_ref)?.get_mut_data_slice(start, length)
     }
 
-    pub fn collect_garbage(&mut self) {
-        let mut scanning_statuses = HashMap::with_capacity(self.reference_map.len());
-        let mut root_objects = Vec::new();
-        for (obj_ref, obj) in &self.reference_map {
-            scanning_statuses.insert(obj_ref.clone(), (IsGarbage::Yes, obj));
-            if obj.stack_references > 0 {
-                root_objects.push(obj_ref);
+    /// Copies `length` bytes of data between objects, which may be the same object with
+    /// overlapping ranges
+    pub fn copy_data(
+        &mut self,
+        source: &ObjectReference, source_start: usize,
+        destination: &ObjectReference, destination_start: usize,
+        length: usize
+    ) -> HeapResult<()> {
+        // copying through a buffer, as source and destination may be the same object
+        let data = self.get_data_slice(source, source_start, length)?.to_vec();
+        self.get_mut_data_slice(destination, destination_start, length)?.copy_from_slice(&data);
+        Ok(())
+    }
+
+    /// Removes every object not reachable from a pinned object or one with stack references,
+    /// including objects which only refer to each other or to themselves, returning what the
+    /// collection scanned and removed
+    ///
+    /// a collection started by [Heap::collect_garbage_incremental] is abandoned
+    pub fn collect_garbage(&mut self) -> GcStats {
+        self.collection = None;
+        self.collect_garbage_incremental(usize::MAX).unwrap()
+    }
+
+    /// Continues a collection, or starts one if none is in progress, examining at most `budget`
+    /// slots and objects before returning
+    ///
+    /// returns the stats of the collection once it finishes, having removed the same objects
+    /// [Heap::collect_garbage] would have when the collection started, and `None` while it is
+    /// still in progress\
+    /// objects allocated, given a stack reference, pinned, or set as a child before the collection
+    /// has found every reachable object are kept until the next collection
+    pub fn collect_garbage_incremental(&mut self, mut budget: usize) -> Option<GcStats> {
+        let mut collection = self.collection.take().unwrap_or_else(|| Collection {
+            reachable: vec![false; self.slots.len()],
+            to_visit: Vec::new(),
+            phase: CollectionPhase::Roots(0),
+            stats: GcStats::default()
+        });
+        loop {
+            match collection.phase {
+                CollectionPhase::Roots(index) if index < self.slots.len() => {
+                    if budget == 0 {
+                        break;
+                    }
+                    budget -= 1;
+                    let slot = &self.slots[index];
+                    if let Some(obj) = &slot.object {
+                        let obj_ref = ObjectReference::from_slot(index as u32, slot.generation);
+                        if obj.stack_references > 0 || self.pinned.contains(&obj_ref) {
+                            collection.mark(obj_ref);
+                        }
+                    }
+                    collection.phase = CollectionPhase::Roots(index + 1);
+                }
+                CollectionPhase::Roots(_) => collection.phase = CollectionPhase::Mark,
+                CollectionPhase::Mark => {
+                    if collection.to_visit.is_empty() {
+                        collection.phase = CollectionPhase::Sweep(0);
+                        continue;
+                    }
+                    if budget == 0 {
+                        break;
+                    }
+                    budget -= 1;
+                    let obj_ref = collection.to_visit.pop().unwrap();
+                    // the object may have been freed since it was marked
+                    if let Ok(obj) = self.get_object(&obj_ref) {
+                        for child in obj.children.iter().flatten() {
+                            if self.get_object(child).is_ok() {
+                                collection.mark(child.clone());
+                            }
+                        }
+                    }
+                }
+                CollectionPhase::Sweep(index) if index < self.slots.len() => {
+                    if budget == 0 {
+                        break;
+                    }
+                    budget -= 1;
+                    let slot = &self.slots[index];
+                    if let Some(obj) = &slot.object {
+                        collection.stats.scanned += 1;
+                        if !collection.reachable[index] {
+                            collection.stats.bytes_reclaimed += obj.allocated_bytes();
+                            collection.stats.reclaimed += 1;
+                            self.remove_object(&ObjectReference::from_slot(index as u32, slot.generation));
+                        }
+                    }
+                    collection.phase = CollectionPhase::Sweep(index + 1);
+                }
+                CollectionPhase::Sweep(_) => {
+                    self.gc_totals.scanned += collection.stats.scanned;
+                    self.gc_totals.reclaimed += collection.stats.reclaimed;
+                    self.gc_totals.bytes_reclaimed += collection.stats.bytes_reclaimed;
+                    return Some(collection.stats);
+                }
             }
         }
-        for root_obj in root_objects {
-            self.sift_garbage(root_obj, &mut scanning_statuses);
+        self.collection = Some(collection);
+        None
+    }
+
+    /// The sum of the stats of every collection finished so far
+    pub fn gc_totals(&self) -> GcStats {
+        self.gc_totals
+    }
+
+    /// Keeps an object through the collection in progress, unless it has already finished marking
+    fn mark_reachable(&mut self, obj_ref: &ObjectReference) {
+        if self.collection.is_none() || self.get_object(obj_ref).is_err() {
+            return;
         }
-        let mut garbage_bin = Vec::new();
-        for (obj_ref, (status, _)) in scanning_statuses {
-            if status == IsGarbage::Yes {
-                garbage_bin.push(obj_ref);
+        if let Some(collection) = &mut self.collection {
+            if !matches!(collection.phase, CollectionPhase::Sweep(_)) {
+                collection.mark(obj_ref.clone());
             }
         }
-        for garbage in garbage_bin {
-            self.reference_map.remove(&garbage);
-        }
     }
+}
 
-    fn sift_garbage(&self, obj_ref: &ObjectReference, scanning_statuses: &mut HashMap<ObjectReference, (IsGarbage, &Object)>) {
-        let (status, obj) = scanning_statuses.get_mut(obj_ref).unwrap();
-        if *status == IsGarbage::Yes {
-            *status = IsGarbage::No;
-            for child_ref in obj.children.iter().flatten() {
-                self.sift_garbage(child_ref, scanning_statuses);
-            }
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone)]
+struct Collection {
+    /// whether the object in each slot has been found reachable
+    reachable: Vec<bool>,
+    /// reachable objects whose children have not been marked yet
+    to_visit: Vec<ObjectReference>,
+    phase: CollectionPhase,
+    stats: GcStats
+}
+
+impl Collection {
+    fn mark(&mut self, obj_ref: ObjectReference) {
+        let index = obj_ref.slot().0;
+        // marked before visiting children, so cycles (including an object being its own child)
+        // are only ever visited once
+        if !self.reachable[index] {
+            self.reachable[index] = true;
+            self.to_visit.push(obj_ref);
         }
     }
 }
 
-#[derive(Eq, PartialEq)]
-enum IsGarbage {
-    Yes,
-    No
+/// What a garbage collection went through, see [Heap::collect_garbage]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// objects on the heap when the collection checked whether to remove them
+    pub scanned: usize,
+    /// objects removed
+    pub reclaimed: usize,
+    /// the [Heap::total_allocated_bytes] freed by removing them
+    pub bytes_reclaimed: usize
+}
+
+#[derive(Clone, Copy)]
+enum CollectionPhase {
+    /// marking pinned objects and objects with stack references, from the given slot on
+    Roots(usize),
+    /// marking the children of marked objects
+    Mark,
+    /// removing unmarked objects, from the given slot on
+    Sweep(usize)
+}
+
+#[derive(Clone)]
+struct Slot {
+    /// incremented each time the slot is emptied, so that references to the objects it held
+    /// before no longer resolve
+    generation: u32,
+    object: Option<Object>
 }
 
 #[derive(Debug)]
 pub enum HeapError {
+    /// The object references ran out, or an object was too large to ever be allocated
     Allocation,
+    OutOfMemory,
     ObjectNotFound,
     StackReferenceError,
     ChildIndexOutOfBounds,
@@ -116,16 +448,60 @@ pub enum HeapError {
     OutOfBoundsObjectDataAccess
 }
 
+impl Display for HeapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HeapError::Allocation => write!(f, "object could not be allocated"),
+            HeapError::OutOfMemory => write!(f, "allocation would exceed the heap's byte limit"),
+            HeapError::ObjectNotFound => write!(f, "object not found"),
+            HeapError::StackReferenceError => write!(f, "object stack reference count out of range"),
+            HeapError::ChildIndexOutOfBounds => write!(f, "child index out of bounds"),
+            HeapError::IllegalNullObjectReferenceUsage => write!(f, "null object reference used where an object is required"),
+            HeapError::OutOfBoundsObjectDataAccess => write!(f, "object data access out of bounds")
+        }
+    }
+}
+
+impl Error for HeapError {}
+
 pub type HeapResult<T> = Result<T, HeapError>;
 
+/// The reserved value of a null object reference, which never refers to an [Object], such as
+/// an empty child slot pushed by `GET_CHILD`
+pub const NULL_REFERENCE: u64 = 0;
+
+/// Whether `n` is the null object reference, see [NULL_REFERENCE]
+pub fn is_null_reference(n: u64) -> bool {
+    n == NULL_REFERENCE
+}
+
+/// A reference to an [Object], never null, the null reference is represented as [None] where it
+/// is allowed
+///
+/// the low 32 bits hold one more than the index of the object's slot in the [Heap], and the high
+/// 32 bits hold the generation of that slot when the object was allocated
 #[derive(Clone, Hash, Eq, PartialEq, Debug)]
 pub struct ObjectReference(NonZeroU64);
 
 impl ObjectReference {
+    fn from_slot(index: u32, generation: u32) -> Self {
+        Self(NonZeroU64::new((generation as u64) << 32 | (index as u64 + 1)).unwrap())
+    }
+
+    /// The index and generation of the slot this reference points to
+    fn slot(&self) -> (usize, u32) {
+        let n = self.0.get();
+        // a low half of 0 can only come from outside the heap, and maps to an index no slot has
+        (((n as u32) as usize).wrapping_sub(1), (n >> 32) as u32)
+    }
+
+    /// The reference `n`, or [None] if it is [NULL_REFERENCE]
     pub fn new_option(n: u64) -> Option<Self> {
         Some(Self(NonZeroU64::new(n)?))
     }
 
+    /// The reference `n`, failing with [HeapError::IllegalNullObjectReferenceUsage] if it is
+    /// [NULL_REFERENCE]
     pub fn new_result(n: u64) -> HeapResult<Self> {
         Self::new_option(n).ok_or(HeapError::IllegalNullObjectReferenceUsage)
     }
@@ -137,10 +513,25 @@ impl From<ObjectReference> for u64 {
     }
 }
 
-#[derive(Debug)]
+impl From<NonZeroU64> for ObjectReference {
+    fn from(n: NonZeroU64) -> Self {
+        Self(n)
+    }
+}
+
+impl Display for ObjectReference {
+    /// Formats the reference as its id, such as `#1`
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Object {
     /// a count of stack references to this object
-    stack_references: u16,
+    stack_references: u64,
+    /// a count of child slots, in this or other objects, holding this object
+    parent_references: usize,
     /// all objects this object refers to
     children: Box<[Option<ObjectReference>]>,
     /// data contained within this object
@@ -148,12 +539,24 @@ pub struct Object {
 }
 
 impl Object {
-    fn new(children_length: usize, data_length: usize) -> Self {
-        Self {
+    /// Fails with [HeapError::Allocation] rather than aborting when the memory can not be reserved
+    fn new(children_length: usize, data_length: usize) -> HeapResult<Self> {
+        let mut children = Vec::new();
+        children.try_reserve_exact(children_length).map_err(|_| HeapError::Allocation)?;
+        children.resize(children_length, None);
+        let mut data = Vec::new();
+        data.try_reserve_exact(data_length).map_err(|_| HeapError::Allocation)?;
+        data.resize(data_length, 0);
+        Ok(Self {
             stack_references: 1,
-            children: vec![None; children_length].into_boxed_slice(),
-            data: vec![0; data_length].into_boxed_slice()
-        }
+            parent_references: 0,
+            children: children.into_boxed_slice(),
+            data: data.into_boxed_slice()
+        })
+    }
+
+    fn allocated_bytes(&self) -> usize {
+        self.data.len() + self.children.len() * 8
     }
 
     fn set_child(&mut self, index: usize, child: Option<&ObjectReference>) -> HeapResult<()> {
@@ -166,10 +569,518 @@ impl Object {
     }
 
     fn get_data_slice(&self, start: usize, length: usize) -> HeapResult<&[u8]> {
-        self.data.get(start..start+length).ok_or(HeapError::OutOfBoundsObjectDataAccess)
+        let end = start.checked_add(length).ok_or(HeapError::OutOfBoundsObjectDataAccess)?;
+        self.data.get(start..end).ok_or(HeapError::OutOfBoundsObjectDataAccess)
     }
 
     fn get_mut_data_slice(&mut self, start: usize, length: usize) -> HeapResult<&mut [u8]> {
-        self.data.get_mut(start..start+length).ok_or(HeapError::OutOfBoundsObjectDataAccess)
+        let end = start.checked_add(length).ok_or(HeapError::OutOfBoundsObjectDataAccess)?;
+        self.data.get_mut(start..end).ok_or(HeapError::OutOfBoundsObjectDataAccess)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn free_removes_an_object_on_its_last_reference() {
+        let mut heap = Heap::new();
+        let obj_ref = heap.allocate(0, 4).unwrap();
+        heap.increment_stack_references(&obj_ref).unwrap();
+        assert!(!heap.free(obj_ref.clone()).unwrap());
+        assert!(heap.get_data_slice(&obj_ref, 0, 4).is_ok());
+        assert!(heap.free(obj_ref.clone()).unwrap());
+        assert!(matches!(heap.get_data_slice(&obj_ref, 0, 4), Err(HeapError::ObjectNotFound)));
+        assert!(matches!(heap.free(obj_ref), Err(HeapError::ObjectNotFound)));
+    }
+
+    #[test]
+    fn free_keeps_an_object_held_as_a_child() {
+        let mut heap = Heap::new();
+        let parent = heap.allocate(1, 0).unwrap();
+        let child = heap.allocate(0, 0).unwrap();
+        heap.set_child(&parent, 0, Some(&child)).unwrap();
+        assert!(!heap.free(child.clone()).unwrap());
+        assert_eq!(heap.get_child(&parent, 0).unwrap(), Some(child.clone()));
+        assert!(heap.get_data_slice(&child, 0, 0).is_ok());
+    }
+
+    #[test]
+    fn unreferenced_cycles_are_collected() {
+        let mut heap = Heap::new();
+        let a = heap.allocate(1, 0).unwrap();
+        let b = heap.allocate(1, 0).unwrap();
+        let own_child = heap.allocate(1, 0).unwrap();
+        heap.set_child(&a, 0, Some(&b)).unwrap();
+        heap.set_child(&b, 0, Some(&a)).unwrap();
+        heap.set_child(&own_child, 0, Some(&own_child)).unwrap();
+        heap.decrement_stack_references(a.clone()).unwrap();
+        heap.decrement_stack_references(b.clone()).unwrap();
+        assert_eq!(heap.collect_garbage().reclaimed, 2);
+        assert!(matches!(heap.get_data_slice(&a, 0, 0), Err(HeapError::ObjectNotFound)));
+        assert!(matches!(heap.get_data_slice(&b, 0, 0), Err(HeapError::ObjectNotFound)));
+        assert_eq!(heap.get_child(&own_child, 0).unwrap(), Some(own_child.clone()));
+        heap.decrement_stack_references(own_child.clone()).unwrap();
+        assert_eq!(heap.collect_garbage().reclaimed, 1);
+        assert_eq!(heap.object_count(), 0);
+    }
+
+    #[test]
+    fn counts_objects_and_allocated_bytes() {
+        let mut heap = Heap::new();
+        assert_eq!(heap.object_count(), 0);
+        assert_eq!(heap.total_allocated_bytes(), 0);
+        heap.allocate(0, 16).unwrap();
+        let pair = heap.allocate(2, 0).unwrap();
+        heap.allocate(1, 3).unwrap();
+        assert_eq!(heap.object_count(), 3);
+        assert_eq!(heap.total_allocated_bytes(), 16 + 2 * 8 + 8 + 3);
+        assert!(heap.free(pair).unwrap());
+        assert_eq!(heap.object_count(), 2);
+        assert_eq!(heap.total_allocated_bytes(), 16 + 8 + 3);
+    }
+
+    #[test]
+    fn allocations_fail_beyond_the_limit() {
+        let mut heap = Heap::with_limit(32);
+        let first = heap.allocate(1, 8).unwrap();
+        heap.allocate(2, 0).unwrap();
+        assert_eq!(heap.total_allocated_bytes(), 32);
+        assert!(matches!(heap.allocate(0, 1), Err(HeapError::OutOfMemory)));
+        assert!(matches!(heap.allocate(usize::MAX, 0), Err(HeapError::Allocation)));
+        assert_eq!(heap.object_count(), 2);
+        // freeing makes room again
+        assert!(heap.free(first).unwrap());
+        heap.allocate(0, 16).unwrap();
+        assert!(matches!(heap.allocate(0, 1), Err(HeapError::OutOfMemory)));
+    }
+
+    #[test]
+    fn alloc_collect_cycles_reuse_slots() {
+        let mut heap = Heap::new();
+        let kept = heap.allocate(0, 0).unwrap();
+        for _ in 0..10_000 {
+            let a = heap.allocate(0, 0).unwrap();
+            let b = heap.allocate(0, 0).unwrap();
+            heap.decrement_stack_references(a).unwrap();
+            heap.decrement_stack_references(b).unwrap();
+            assert_eq!(heap.collect_garbage().reclaimed, 2);
+        }
+        assert_eq!(heap.slots.len(), 3);
+        assert_eq!(heap.object_count(), 1);
+        assert_eq!(heap.data_len(&kept).unwrap(), 0);
+    }
+
+    #[test]
+    fn freed_references_do_not_reach_new_objects() {
+        let mut heap = Heap::new();
+        let freed = heap.allocate(0, 1).unwrap();
+        assert!(heap.free(freed.clone()).unwrap());
+        let reused = heap.allocate(0, 2).unwrap();
+        assert_eq!(u64::from(reused.clone()) as u32, u64::from(freed.clone()) as u32);
+        assert_ne!(reused, freed);
+        assert!(matches!(heap.get_data_slice(&freed, 0, 0), Err(HeapError::ObjectNotFound)));
+        assert!(matches!(heap.increment_stack_references(&freed), Err(HeapError::ObjectNotFound)));
+        assert!(heap.get_data_slice(&reused, 0, 2).is_ok());
+    }
+
+    #[test]
+    fn double_free_fails_without_touching_new_objects() {
+        let mut heap = Heap::new();
+        let obj_ref = heap.allocate(0, 0).unwrap();
+        assert!(heap.free(obj_ref.clone()).unwrap());
+        assert!(matches!(heap.free(obj_ref.clone()), Err(HeapError::ObjectNotFound)));
+        let reused = heap.allocate(0, 0).unwrap();
+        assert!(matches!(heap.free(obj_ref), Err(HeapError::ObjectNotFound)));
+        assert_eq!(heap.object_count(), 1);
+        assert!(heap.free(reused).unwrap());
+    }
+
+    #[test]
+    fn slots_out_of_generations_are_retired() {
+        let mut heap = Heap::new();
+        let obj_ref = heap.allocate(0, 0).unwrap();
+        heap.slots[0].generation = u32::MAX;
+        let last = ObjectReference::from_slot(0, u32::MAX);
+        assert!(heap.free(last).unwrap());
+        assert!(heap.free_slots.is_empty());
+        let next = heap.allocate(0, 0).unwrap();
+        assert_eq!(next.slot().0, 1);
+        assert!(heap.data_len(&obj_ref).is_err());
+    }
+
+    #[test]
+    fn data_accesses_past_the_end_fail() {
+        let mut heap = Heap::new();
+        let obj_ref = heap.allocate(0, 8).unwrap();
+        assert!(heap.get_data_slice(&obj_ref, 8, 0).unwrap().is_empty());
+        assert!(matches!(heap.get_data_slice(&obj_ref, 4, 5), Err(HeapError::OutOfBoundsObjectDataAccess)));
+        assert!(matches!(heap.get_data_slice(&obj_ref, usize::MAX, 1), Err(HeapError::OutOfBoundsObjectDataAccess)));
+        assert!(matches!(heap.get_mut_data_slice(&obj_ref, usize::MAX, 8), Err(HeapError::OutOfBoundsObjectDataAccess)));
+    }
+
+    #[test]
+    fn heap_errors_display_readably() {
+        let cases = [
+            (HeapError::Allocation, "object could not be allocated"),
+            (HeapError::OutOfMemory, "allocation would exceed the heap's byte limit"),
+            (HeapError::ObjectNotFound, "object not found"),
+            (HeapError::StackReferenceError, "object stack reference count out of range"),
+            (HeapError::ChildIndexOutOfBounds, "child index out of bounds"),
+            (HeapError::IllegalNullObjectReferenceUsage, "null object reference used where an object is required"),
+            (HeapError::OutOfBoundsObjectDataAccess, "object data access out of bounds")
+        ];
+        for (e, expected) in cases {
+            assert_eq!(e.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn copy_data_between_objects() {
+        let mut heap = Heap::new();
+        let source = heap.allocate(0, 4).unwrap();
+        let destination = heap.allocate(0, 6).unwrap();
+        heap.get_mut_data_slice(&source, 0, 4).unwrap().copy_from_slice(&[1, 2, 3, 4]);
+        heap.copy_data(&source, 1, &destination, 2, 3).unwrap();
+        assert_eq!(heap.get_data_slice(&destination, 0, 6).unwrap(), &[0, 0, 2, 3, 4, 0]);
+        assert!(matches!(
+            heap.copy_data(&source, 2, &destination, 0, 3),
+            Err(HeapError::OutOfBoundsObjectDataAccess)
+        ));
+    }
+
+    #[test]
+    fn copy_data_within_an_object_may_overlap() {
+        let mut heap = Heap::new();
+        let obj_ref = heap.allocate(0, 6).unwrap();
+        heap.get_mut_data_slice(&obj_ref, 0, 6).unwrap().copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        heap.copy_data(&obj_ref, 0, &obj_ref, 2, 4).unwrap();
+        assert_eq!(heap.get_data_slice(&obj_ref, 0, 6).unwrap(), &[1, 2, 1, 2, 3, 4]);
+        heap.copy_data(&obj_ref, 2, &obj_ref, 0, 4).unwrap();
+        assert_eq!(heap.get_data_slice(&obj_ref, 0, 6).unwrap(), &[1, 2, 3, 4, 3, 4]);
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let mut heap = Heap::new();
+        let parent = heap.allocate(1, 4).unwrap();
+        let child = heap.allocate(0, 0).unwrap();
+        heap.set_child(&parent, 0, Some(&child)).unwrap();
+        heap.get_mut_data_slice(&parent, 0, 4).unwrap().copy_from_slice(&[1, 2, 3, 4]);
+        let mut clone = heap.clone();
+        clone.get_mut_data_slice(&parent, 0, 4).unwrap().copy_from_slice(&[9, 9, 9, 9]);
+        clone.set_child(&parent, 0, None).unwrap();
+        clone.allocate(0, 100).unwrap();
+        assert_eq!(heap.get_data_slice(&parent, 0, 4).unwrap(), &[1, 2, 3, 4]);
+        assert_eq!(heap.get_child(&parent, 0).unwrap(), Some(child));
+        assert_eq!(heap.object_count(), 2);
+        assert_eq!(heap.total_allocated_bytes(), 12);
+        assert_eq!(clone.get_data_slice(&parent, 0, 4).unwrap(), &[9, 9, 9, 9]);
+    }
+
+    #[test]
+    fn rejects_objects_larger_than_isize_max() {
+        let mut heap = Heap::new();
+        assert!(matches!(heap.allocate(0, isize::MAX as usize + 1), Err(HeapError::Allocation)));
+        assert!(matches!(heap.allocate(isize::MAX as usize / 8 + 1, 0), Err(HeapError::Allocation)));
+        assert!(matches!(heap.allocate(usize::MAX, 0), Err(HeapError::Allocation)));
+        assert!(matches!(heap.allocate(1, isize::MAX as usize - 7), Err(HeapError::Allocation)));
+        assert_eq!(heap.object_count(), 0);
+        assert_eq!(heap.total_allocated_bytes(), 0);
+    }
+
+    #[test]
+    fn unreservable_objects_fail_without_aborting() {
+        let mut heap = Heap::new();
+        // at the isize::MAX boundary, and below it but still far beyond any memory
+        assert!(matches!(heap.allocate(0, isize::MAX as usize), Err(HeapError::Allocation)));
+        assert!(matches!(heap.allocate(isize::MAX as usize / 8, 0), Err(HeapError::Allocation)));
+        assert!(matches!(heap.allocate(1 << 58, 0), Err(HeapError::Allocation)));
+        assert_eq!(heap.object_count(), 0);
+        assert_eq!(heap.total_allocated_bytes(), 0);
+        let obj_ref = heap.allocate(1, 1).unwrap();
+        assert_eq!(u64::from(obj_ref) & 0xFFFF_FFFF, 1);
+    }
+
+    #[test]
+    fn stack_reference_counts_are_checked() {
+        let mut heap = Heap::new();
+        let obj_ref = heap.allocate(0, 0).unwrap();
+        heap.get_mut_object(&obj_ref).unwrap().stack_references = u64::MAX;
+        assert!(matches!(heap.increment_stack_references(&obj_ref), Err(HeapError::StackReferenceError)));
+        heap.get_mut_object(&obj_ref).unwrap().stack_references = 0;
+        assert!(matches!(heap.decrement_stack_references(obj_ref), Err(HeapError::StackReferenceError)));
+    }
+
+    #[test]
+    fn auto_collect_removes_objects_on_release() {
+        for auto_collect in [false, true] {
+            let mut heap = Heap::new();
+            heap.set_auto_collect(auto_collect);
+            for _ in 0..100 {
+                let obj_ref = heap.allocate(0, 0).unwrap();
+                heap.decrement_stack_references(obj_ref).unwrap();
+            }
+            let expected = if auto_collect { 0 } else { 100 };
+            assert_eq!(heap.object_count(), expected);
+            assert_eq!(heap.collect_garbage().reclaimed, expected);
+        }
+    }
+
+    #[test]
+    fn auto_collect_keeps_objects_still_referenced_as_children() {
+        let mut heap = Heap::new();
+        heap.set_auto_collect(true);
+        let parent = heap.allocate(1, 0).unwrap();
+        let child = heap.allocate(0, 0).unwrap();
+        heap.set_child(&parent, 0, Some(&child)).unwrap();
+        heap.decrement_stack_references(child.clone()).unwrap();
+        assert_eq!(heap.object_count(), 2);
+        assert_eq!(heap.data_len(&child).unwrap(), 0);
+        heap.decrement_stack_references(parent).unwrap();
+        assert_eq!(heap.object_count(), 1);
+        assert_eq!(heap.collect_garbage().reclaimed, 1);
+        assert!(matches!(heap.data_len(&child), Err(HeapError::ObjectNotFound)));
+    }
+
+    #[test]
+    fn overwritten_children_stop_counting_as_references() {
+        let mut heap = Heap::new();
+        heap.set_auto_collect(true);
+        let a = heap.allocate(1, 0).unwrap();
+        let b = heap.allocate(2, 0).unwrap();
+        let child = heap.allocate(0, 0).unwrap();
+        heap.set_child(&a, 0, Some(&child)).unwrap();
+        heap.set_child(&b, 0, Some(&child)).unwrap();
+        heap.set_child(&b, 1, Some(&child)).unwrap();
+        heap.set_child(&b, 1, Some(&a)).unwrap();
+        heap.set_child(&a, 0, None).unwrap();
+        assert!(!heap.free(child.clone()).unwrap());
+        heap.increment_stack_references(&child).unwrap();
+        heap.set_child(&b, 0, None).unwrap();
+        assert!(heap.free(child).unwrap());
+        assert!(!heap.free(a.clone()).unwrap());
+        assert!(heap.free(b).unwrap());
+        assert_eq!(heap.object_count(), 1);
+        assert_eq!(heap.collect_garbage().reclaimed, 1);
+    }
+
+    #[test]
+    fn set_child_rejects_missing_children() {
+        let mut heap = Heap::new();
+        let parent = heap.allocate(1, 0).unwrap();
+        let child = heap.allocate(0, 0).unwrap();
+        assert!(heap.free(child.clone()).unwrap());
+        assert!(matches!(heap.set_child(&parent, 0, Some(&child)), Err(HeapError::ObjectNotFound)));
+        assert_eq!(heap.get_child(&parent, 0).unwrap(), None);
+    }
+
+    #[test]
+    fn live_references_lists_the_surviving_objects() {
+        let mut heap = Heap::new();
+        let kept = heap.allocate(1, 0).unwrap();
+        let child = heap.allocate(0, 0).unwrap();
+        let dropped = heap.allocate(0, 0).unwrap();
+        heap.set_child(&kept, 0, Some(&child)).unwrap();
+        heap.decrement_stack_references(child.clone()).unwrap();
+        heap.decrement_stack_references(dropped).unwrap();
+        assert_eq!(heap.collect_garbage().reclaimed, 1);
+        let live: HashSet<_> = heap.live_references().collect();
+        assert_eq!(live, HashSet::from([kept, child]));
+    }
+
+    #[test]
+    fn references_display_as_ids() {
+        let obj_ref = ObjectReference::from(NonZeroU64::new(1).unwrap());
+        assert_eq!(obj_ref.to_string(), "#1");
+        assert_eq!(u64::from(obj_ref), 1);
+        let mut heap = Heap::new();
+        let allocated = heap.allocate(0, 0).unwrap();
+        assert_eq!(allocated, ObjectReference::from(NonZeroU64::new(1).unwrap()));
+    }
+
+    #[test]
+    fn zero_is_the_null_reference() {
+        assert!(is_null_reference(0));
+        assert!(!is_null_reference(1));
+        assert!(!is_null_reference(u64::MAX));
+        assert!(ObjectReference::new_option(NULL_REFERENCE).is_none());
+        assert!(matches!(ObjectReference::new_result(NULL_REFERENCE), Err(HeapError::IllegalNullObjectReferenceUsage)));
+    }
+
+    #[test]
+    fn references_survive_slot_reallocation() {
+        let mut heap = Heap::new();
+        let first = heap.allocate(1, 8).unwrap();
+        heap.get_mut_data_slice(&first, 0, 8).unwrap().copy_from_slice(&u64::MAX.to_le_bytes());
+        let capacity = heap.slots.capacity();
+        let references: Vec<ObjectReference> = (0..10_000u64).map(|i| {
+            let obj_ref = heap.allocate(1, 8).unwrap();
+            heap.get_mut_data_slice(&obj_ref, 0, 8).unwrap().copy_from_slice(&i.to_le_bytes());
+            heap.set_child(&obj_ref, 0, Some(&first)).unwrap();
+            obj_ref
+        }).collect();
+        assert!(heap.slots.capacity() > capacity);
+        assert_eq!(heap.get_data_slice(&first, 0, 8).unwrap(), &u64::MAX.to_le_bytes());
+        for (i, obj_ref) in references.iter().enumerate() {
+            assert_eq!(heap.get_data_slice(obj_ref, 0, 8).unwrap(), &(i as u64).to_le_bytes());
+            assert_eq!(heap.get_child(obj_ref, 0).unwrap(), Some(first.clone()));
+        }
+    }
+
+    #[test]
+    fn stale_references_fail_cleanly() {
+        let mut heap = Heap::new();
+        let holder = heap.allocate(1, 0).unwrap();
+        let stale = heap.allocate(1, 4).unwrap();
+        assert!(heap.free(stale.clone()).unwrap());
+        let reused = heap.allocate(1, 4).unwrap();
+        assert_eq!(reused.slot().0, stale.slot().0);
+        assert!(matches!(heap.get_data_slice(&stale, 0, 4), Err(HeapError::ObjectNotFound)));
+        assert!(matches!(heap.get_mut_data_slice(&stale, 0, 4), Err(HeapError::ObjectNotFound)));
+        assert!(matches!(heap.get_child(&stale, 0), Err(HeapError::ObjectNotFound)));
+        assert!(matches!(heap.set_child(&stale, 0, None), Err(HeapError::ObjectNotFound)));
+        assert!(matches!(heap.children_len(&stale), Err(HeapError::ObjectNotFound)));
+        assert!(matches!(heap.pin(&stale), Err(HeapError::ObjectNotFound)));
+        assert!(matches!(heap.decrement_stack_references(stale.clone()), Err(HeapError::ObjectNotFound)));
+        assert!(!heap.contains(&stale));
+        let never_allocated = ObjectReference::from_slot(1_000, 0);
+        assert!(matches!(heap.data_len(&never_allocated), Err(HeapError::ObjectNotFound)));
+        // a stale child is rejected, and never resolves to the object reusing its slot
+        assert!(matches!(heap.set_child(&holder, 0, Some(&stale)), Err(HeapError::ObjectNotFound)));
+        assert_eq!(heap.get_child(&holder, 0).unwrap(), None);
+        assert_eq!(heap.data_len(&reused).unwrap(), 4);
+        assert!(heap.free(reused).unwrap());
+        assert_eq!(heap.object_count(), 1);
+    }
+
+    /// A heap holding a rooted chain, an unrooted chain and an unrooted cycle
+    fn garbage_heap() -> Heap {
+        let mut heap = Heap::new();
+        let chain = |heap: &mut Heap, rooted: bool| {
+            let root = heap.allocate(1, 0).unwrap();
+            let middle = heap.allocate(1, 0).unwrap();
+            let leaf = heap.allocate(0, 4).unwrap();
+            heap.set_child(&root, 0, Some(&middle)).unwrap();
+            heap.set_child(&middle, 0, Some(&leaf)).unwrap();
+            heap.decrement_stack_references(middle).unwrap();
+            heap.decrement_stack_references(leaf).unwrap();
+            if !rooted {
+                heap.decrement_stack_references(root).unwrap();
+            }
+        };
+        chain(&mut heap, true);
+        chain(&mut heap, false);
+        let a = heap.allocate(1, 0).unwrap();
+        let b = heap.allocate(1, 0).unwrap();
+        heap.set_child(&a, 0, Some(&b)).unwrap();
+        heap.set_child(&b, 0, Some(&a)).unwrap();
+        heap.decrement_stack_references(a).unwrap();
+        heap.decrement_stack_references(b).unwrap();
+        heap
+    }
+
+    #[test]
+    fn budgeted_collections_reclaim_what_a_full_collection_does() {
+        let mut full = garbage_heap();
+        assert_eq!(full.collect_garbage().reclaimed, 5);
+        let expected: HashSet<ObjectReference> = full.live_references().collect();
+        for budget in [1, 2, 3, 7, 100] {
+            let mut heap = garbage_heap();
+            let mut calls = 0;
+            let collected = loop {
+                calls += 1;
+                if let Some(collected) = heap.collect_garbage_incremental(budget) {
+                    break collected.reclaimed;
+                }
+            };
+            assert_eq!(collected, 5, "budget {}", budget);
+            assert_eq!(heap.live_references().collect::<HashSet<_>>(), expected, "budget {}", budget);
+            if budget == 1 {
+                assert!(calls > 10);
+            }
+        }
+    }
+
+    #[test]
+    fn objects_reached_during_a_collection_are_kept() {
+        let mut heap = Heap::new();
+        let root = heap.allocate(1, 0).unwrap();
+        let garbage = heap.allocate(0, 0).unwrap();
+        heap.decrement_stack_references(garbage).unwrap();
+        assert!(heap.collect_garbage_incremental(1).is_none());
+        // allocated while the collection is in progress, then only held as a child
+        let child = heap.allocate(0, 0).unwrap();
+        heap.set_child(&root, 0, Some(&child)).unwrap();
+        heap.decrement_stack_references(child.clone()).unwrap();
+        let collected = loop {
+            if let Some(collected) = heap.collect_garbage_incremental(1) {
+                break collected.reclaimed;
+            }
+        };
+        assert_eq!(collected, 1);
+        assert!(heap.contains(&root));
+        assert!(heap.contains(&child));
+        // a full collection abandons the incremental one in progress
+        assert!(heap.collect_garbage_incremental(1).is_none());
+        assert_eq!(heap.collect_garbage().reclaimed, 0);
+        assert!(heap.collection.is_none());
+    }
+
+    #[test]
+    fn collections_report_their_stats() {
+        let mut heap = Heap::new();
+        let parent = heap.allocate(1, 8).unwrap();
+        let child = heap.allocate(0, 4).unwrap();
+        heap.set_child(&parent, 0, Some(&child)).unwrap();
+        heap.decrement_stack_references(parent).unwrap();
+        heap.decrement_stack_references(child).unwrap();
+        let kept = heap.allocate(0, 2).unwrap();
+        assert_eq!(heap.collect_garbage(), GcStats { scanned: 3, reclaimed: 2, bytes_reclaimed: 20 });
+        assert_eq!(heap.total_allocated_bytes(), 2);
+        assert_eq!(heap.collect_garbage(), GcStats { scanned: 1, reclaimed: 0, bytes_reclaimed: 0 });
+        heap.decrement_stack_references(kept).unwrap();
+        while heap.collect_garbage_incremental(1).is_none() {}
+        assert_eq!(heap.gc_totals(), GcStats { scanned: 5, reclaimed: 3, bytes_reclaimed: 22 });
+        assert_eq!(heap.object_count(), 0);
+    }
+
+    #[test]
+    fn pinned_objects_survive_collection() {
+        let mut heap = Heap::new();
+        let pinned = heap.allocate(1, 0).unwrap();
+        let child = heap.allocate(0, 0).unwrap();
+        let unpinned = heap.allocate(0, 0).unwrap();
+        heap.set_child(&pinned, 0, Some(&child)).unwrap();
+        heap.pin(&pinned).unwrap();
+        heap.pin(&pinned).unwrap();
+        heap.decrement_stack_references(pinned.clone()).unwrap();
+        heap.decrement_stack_references(child.clone()).unwrap();
+        heap.decrement_stack_references(unpinned.clone()).unwrap();
+        assert_eq!(heap.collect_garbage().reclaimed, 1);
+        assert!(heap.contains(&pinned));
+        assert!(heap.contains(&child));
+        assert!(!heap.contains(&unpinned));
+        assert!(heap.unpin(&pinned));
+        assert!(!heap.unpin(&pinned));
+        assert_eq!(heap.collect_garbage().reclaimed, 2);
+        assert!(matches!(heap.pin(&pinned), Err(HeapError::ObjectNotFound)));
+    }
+
+    #[test]
+    fn freeing_a_pinned_object_keeps_it() {
+        let mut heap = Heap::new();
+        heap.set_auto_collect(true);
+        let obj_ref = heap.allocate(0, 0).unwrap();
+        heap.pin(&obj_ref).unwrap();
+        assert!(!heap.free(obj_ref.clone()).unwrap());
+        assert!(heap.contains(&obj_ref));
+        heap.increment_stack_references(&obj_ref).unwrap();
+        heap.unpin(&obj_ref);
+        heap.decrement_stack_references(obj_ref.clone()).unwrap();
+        assert!(!heap.contains(&obj_ref));
     }
 }
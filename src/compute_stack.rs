@@ -1,4 +1,6 @@
-use std::fmt::Debug;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
 
 macro_rules! push_impl {
     ($name: ident, $typ: ty) => {
@@ -16,7 +18,9 @@ macro_rules! pop_impl {
     ($name: ident, $typ: ty, $bytes: literal) => {
         #[inline(always)]
         fn $name(&mut self) -> StackResult<$typ> {
-            Ok(<$typ>::from_le_bytes((&*self.pop_slice($bytes)?).try_into().unwrap()))
+            let mut bytes = [0; $bytes];
+            self.pop_into(&mut bytes)?;
+            Ok(<$typ>::from_le_bytes(bytes))
         }
     };
     ($(($name: ident, $typ: ty, $bytes: literal)),+) => {
@@ -24,26 +28,96 @@ macro_rules! pop_impl {
     };
 }
 
+macro_rules! peek_impl {
+    ($name: ident, $typ: ty, $bytes: literal) => {
+        #[inline(always)]
+        fn $name(&self) -> StackResult<$typ> {
+            Ok(<$typ>::from_le_bytes(self.peek_slice($bytes)?.try_into().unwrap()))
+        }
+    };
+    ($(($name: ident, $typ: ty, $bytes: literal)),+) => {
+        $(peek_impl!($name, $typ, $bytes);)+
+    };
+}
+
 pub trait ComputeStack: Debug {
     fn size(&self) -> usize;
 
     fn push_slice(&mut self, slice: &[u8]) -> StackResult<()>;
 
+    /// Hints that at least `additional` more bytes are about to be pushed, so that stacks which
+    /// grow can do so once up front
+    fn reserve(&mut self, additional: usize) {
+        let _ = additional;
+    }
+
     push_impl! {
         (push_u8, u8), (push_u16, u16), (push_u32, u32), (push_u64, u64),
         (push_i8, i8), (push_i16, i16), (push_i32, i32), (push_i64, i64),
         (push_f32, f32), (push_f64, f64)
     }
 
+    /// Pushes each slice in order, so the last slice ends up on top
+    ///
+    /// if a push fails, the slices before it stay pushed
+    fn push_all(&mut self, slices: &[&[u8]]) -> StackResult<()> {
+        for slice in slices {
+            self.push_slice(slice)?;
+        }
+        Ok(())
+    }
+
     fn pop_slice(&mut self, length: usize) -> StackResult<Box<[u8]>>;
 
+    /// Pops the top `buf.len()` bytes into `buf`, which unlike [ComputeStack::pop_slice] does not
+    /// allocate
+    fn pop_into(&mut self, buf: &mut [u8]) -> StackResult<()> {
+        buf.copy_from_slice(self.peek_slice(buf.len())?);
+        self.remove_top(buf.len())
+    }
+
     pop_impl! {
         (pop_u8, u8, 1), (pop_u16, u16, 2), (pop_u32, u32, 4), (pop_u64, u64, 8),
         (pop_i8, i8, 1), (pop_i16, i16, 2), (pop_i32, i32, 4), (pop_i64, i64, 8),
         (pop_f32, f32, 4), (pop_f64, f64, 8)
     }
 
+    /// Pops `n` u64s, returning them in the order they were popped, so the previous top of the
+    /// stack comes first
+    ///
+    /// fails with [StackError::Underflow] without popping anything if fewer than `n` u64s are on
+    /// the stack
+    fn pop_n_u64(&mut self, n: usize) -> StackResult<Vec<u64>> {
+        if n.checked_mul(8).is_none_or(|bytes| bytes > self.size()) {
+            return Err(StackError::Underflow);
+        }
+        (0..n).map(|_| self.pop_u64()).collect()
+    }
+
+    /// Returns the top `length` bytes without removing them
+    fn peek_slice(&self, length: usize) -> StackResult<&[u8]>;
+
+    peek_impl! {
+        (peek_u8, u8, 1), (peek_u16, u16, 2), (peek_u32, u32, 4), (peek_u64, u64, 8),
+        (peek_i8, i8, 1), (peek_i16, i16, 2), (peek_i32, i32, 4), (peek_i64, i64, 8),
+        (peek_f32, f32, 4), (peek_f64, f64, 8)
+    }
+
     fn remove_top(&mut self, length: usize) -> StackResult<()>;
+
+    /// Returns the `length` bytes starting `offset` bytes above the bottom of the stack, failing
+    /// with [StackError::Underflow] if they are not all on the stack
+    fn read_at(&self, offset: usize, length: usize) -> StackResult<&[u8]>;
+
+    /// Overwrites the bytes starting `offset` bytes above the bottom of the stack with `data`,
+    /// failing with [StackError::Underflow] if they are not all on the stack
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> StackResult<()>;
+
+    fn clear(&mut self);
+
+    /// Removes bytes from the top until `size` bytes remain, failing with
+    /// [StackError::Underflow] if fewer than `size` bytes are present
+    fn truncate_to(&mut self, size: usize) -> StackResult<()>;
 }
 
 impl ComputeStack for Vec<u8> {
@@ -57,6 +131,10 @@ impl ComputeStack for Vec<u8> {
         Ok(())
     }
 
+    fn reserve(&mut self, additional: usize) {
+        Vec::reserve(self, additional);
+    }
+
     // overriding since this is presumably faster than the default implementation
     fn push_u8(&mut self, value: u8) -> StackResult<()> {
         self.push(value);
@@ -73,15 +151,146 @@ impl ComputeStack for Vec<u8> {
         }
     }
 
+    fn pop_into(&mut self, buf: &mut [u8]) -> StackResult<()> {
+        let len = self.len();
+        if len >= buf.len() {
+            buf.copy_from_slice(&self[(len - buf.len())..]);
+            self.truncate(len - buf.len());
+            Ok(())
+        } else {
+            Err(StackError::Underflow)
+        }
+    }
+
     // overriding since this is presumably faster than the default implementation
     fn pop_u8(&mut self) -> StackResult<u8> {
         self.pop().ok_or(StackError::Underflow)
     }
 
+    fn peek_slice(&self, length: usize) -> StackResult<&[u8]> {
+        let len = self.len();
+        if len >= length {
+            Ok(&self[(len - length)..])
+        } else {
+            Err(StackError::Underflow)
+        }
+    }
+
     fn remove_top(&mut self, length: usize) -> StackResult<()> {
         let len = self.len();
         if len >= length {
-            self.drain((len - length)..len);
+            self.truncate(len - length);
+            Ok(())
+        } else {
+            Err(StackError::Underflow)
+        }
+    }
+
+    fn read_at(&self, offset: usize, length: usize) -> StackResult<&[u8]> {
+        let end = offset.checked_add(length).ok_or(StackError::Underflow)?;
+        self.get(offset..end).ok_or(StackError::Underflow)
+    }
+
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> StackResult<()> {
+        let end = offset.checked_add(data.len()).ok_or(StackError::Underflow)?;
+        self.get_mut(offset..end).ok_or(StackError::Underflow)?.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self);
+    }
+
+    fn truncate_to(&mut self, size: usize) -> StackResult<()> {
+        if self.len() >= size {
+            self.truncate(size);
+            Ok(())
+        } else {
+            Err(StackError::Underflow)
+        }
+    }
+}
+
+/// A [ComputeStack] storing up to `N` bytes inline, which fails pushes beyond that with
+/// [StackError::Overflow]
+#[derive(Debug)]
+pub struct ArrayStack<const N: usize> {
+    buffer: [u8; N],
+    len: usize
+}
+
+impl<const N: usize> ArrayStack<N> {
+    pub fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            len: 0
+        }
+    }
+}
+
+impl<const N: usize> Default for ArrayStack<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ComputeStack for ArrayStack<N> {
+    #[inline(always)]
+    fn size(&self) -> usize {
+        self.len
+    }
+
+    fn push_slice(&mut self, slice: &[u8]) -> StackResult<()> {
+        if N - self.len >= slice.len() {
+            self.buffer[self.len..self.len + slice.len()].copy_from_slice(slice);
+            self.len += slice.len();
+            Ok(())
+        } else {
+            Err(StackError::Overflow)
+        }
+    }
+
+    fn pop_slice(&mut self, length: usize) -> StackResult<Box<[u8]>> {
+        let popped = self.peek_slice(length)?.into();
+        self.len -= length;
+        Ok(popped)
+    }
+
+    fn peek_slice(&self, length: usize) -> StackResult<&[u8]> {
+        if self.len >= length {
+            Ok(&self.buffer[(self.len - length)..self.len])
+        } else {
+            Err(StackError::Underflow)
+        }
+    }
+
+    fn remove_top(&mut self, length: usize) -> StackResult<()> {
+        if self.len >= length {
+            self.len -= length;
+            Ok(())
+        } else {
+            Err(StackError::Underflow)
+        }
+    }
+
+    fn read_at(&self, offset: usize, length: usize) -> StackResult<&[u8]> {
+        let end = offset.checked_add(length).ok_or(StackError::Underflow)?;
+        self.buffer[..self.len].get(offset..end).ok_or(StackError::Underflow)
+    }
+
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> StackResult<()> {
+        let end = offset.checked_add(data.len()).ok_or(StackError::Underflow)?;
+        self.buffer[..self.len].get_mut(offset..end).ok_or(StackError::Underflow)?.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn truncate_to(&mut self, size: usize) -> StackResult<()> {
+        if self.len >= size {
+            self.len = size;
             Ok(())
         } else {
             Err(StackError::Underflow)
@@ -89,10 +298,377 @@ impl ComputeStack for Vec<u8> {
     }
 }
 
+/// A [ComputeStack] which fails pushes with [StackError::Overflow] once the wrapped stack would
+/// hold more than `capacity` bytes
+#[derive(Debug)]
+pub struct BoundedStack<S: ComputeStack> {
+    stack: S,
+    capacity: usize
+}
+
+impl<S: ComputeStack> BoundedStack<S> {
+    pub fn new(stack: S, capacity: usize) -> Self {
+        Self {
+            stack,
+            capacity
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<S: ComputeStack> ComputeStack for BoundedStack<S> {
+    #[inline(always)]
+    fn size(&self) -> usize {
+        self.stack.size()
+    }
+
+    fn push_slice(&mut self, slice: &[u8]) -> StackResult<()> {
+        if self.capacity.saturating_sub(self.stack.size()) >= slice.len() {
+            self.stack.push_slice(slice)
+        } else {
+            Err(StackError::Overflow)
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        // never reserving more than could be pushed
+        self.stack.reserve(additional.min(self.capacity.saturating_sub(self.stack.size())));
+    }
+
+    fn pop_slice(&mut self, length: usize) -> StackResult<Box<[u8]>> {
+        self.stack.pop_slice(length)
+    }
+
+    fn pop_into(&mut self, buf: &mut [u8]) -> StackResult<()> {
+        self.stack.pop_into(buf)
+    }
+
+    fn pop_u8(&mut self) -> StackResult<u8> {
+        self.stack.pop_u8()
+    }
+
+    fn peek_slice(&self, length: usize) -> StackResult<&[u8]> {
+        self.stack.peek_slice(length)
+    }
+
+    fn remove_top(&mut self, length: usize) -> StackResult<()> {
+        self.stack.remove_top(length)
+    }
+
+    fn read_at(&self, offset: usize, length: usize) -> StackResult<&[u8]> {
+        self.stack.read_at(offset, length)
+    }
+
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> StackResult<()> {
+        self.stack.write_at(offset, data)
+    }
+
+    fn clear(&mut self) {
+        self.stack.clear()
+    }
+
+    fn truncate_to(&mut self, size: usize) -> StackResult<()> {
+        self.stack.truncate_to(size)
+    }
+}
+
+// lets a boxed stack, such as Box<dyn ComputeStack>, be used wherever a ComputeStack is expected
+impl<S: ComputeStack + ?Sized> ComputeStack for Box<S> {
+    #[inline(always)]
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+
+    fn push_slice(&mut self, slice: &[u8]) -> StackResult<()> {
+        (**self).push_slice(slice)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        (**self).reserve(additional)
+    }
+
+    fn push_u8(&mut self, value: u8) -> StackResult<()> {
+        (**self).push_u8(value)
+    }
+
+    fn pop_slice(&mut self, length: usize) -> StackResult<Box<[u8]>> {
+        (**self).pop_slice(length)
+    }
+
+    fn pop_into(&mut self, buf: &mut [u8]) -> StackResult<()> {
+        (**self).pop_into(buf)
+    }
+
+    fn pop_u8(&mut self) -> StackResult<u8> {
+        (**self).pop_u8()
+    }
+
+    fn peek_slice(&self, length: usize) -> StackResult<&[u8]> {
+        (**self).peek_slice(length)
+    }
+
+    fn remove_top(&mut self, length: usize) -> StackResult<()> {
+        (**self).remove_top(length)
+    }
+
+    fn read_at(&self, offset: usize, length: usize) -> StackResult<&[u8]> {
+        (**self).read_at(offset, length)
+    }
+
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> StackResult<()> {
+        (**self).write_at(offset, data)
+    }
+
+    fn clear(&mut self) {
+        (**self).clear()
+    }
+
+    fn truncate_to(&mut self, size: usize) -> StackResult<()> {
+        (**self).truncate_to(size)
+    }
+}
+
 #[derive(Debug)]
 pub enum StackError {
     Underflow,
     Overflow
 }
 
+impl Display for StackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StackError::Underflow => write!(f, "stack underflow"),
+            StackError::Overflow => write!(f, "stack overflow")
+        }
+    }
+}
+
+impl Error for StackError {}
+
 pub type StackResult<T> = Result<T, StackError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peek_leaves_the_stack_unchanged() {
+        let mut stack = Vec::new();
+        stack.push_u32(0xDEADBEEF).unwrap();
+        stack.push_i16(-2).unwrap();
+        assert_eq!(stack.peek_i16().unwrap(), -2);
+        assert_eq!(stack.peek_u8().unwrap(), 0xFF);
+        assert_eq!(stack.peek_slice(6).unwrap(), &[0xEF, 0xBE, 0xAD, 0xDE, 0xFE, 0xFF]);
+        assert!(stack.peek_slice(0).unwrap().is_empty());
+        assert_eq!(stack.size(), 6);
+        assert_eq!(stack.pop_i16().unwrap(), -2);
+        assert_eq!(stack.peek_u32().unwrap(), 0xDEADBEEF);
+        assert_eq!(stack.size(), 4);
+    }
+
+    #[test]
+    fn peek_past_the_bottom_underflows() {
+        let mut stack = Vec::new();
+        assert!(matches!(stack.peek_u8(), Err(StackError::Underflow)));
+        stack.push_u32(1).unwrap();
+        assert!(matches!(stack.peek_u64(), Err(StackError::Underflow)));
+        assert!(matches!(stack.peek_slice(5), Err(StackError::Underflow)));
+        assert_eq!(stack.size(), 4);
+    }
+
+    #[test]
+    fn bounded_stack_overflows_past_its_capacity() {
+        let mut stack = BoundedStack::new(Vec::new(), 12);
+        stack.push_u64(1).unwrap();
+        stack.push_u16(2).unwrap();
+        stack.push_u8(3).unwrap();
+        stack.push_u8(4).unwrap();
+        assert_eq!(stack.size(), stack.capacity());
+        assert!(matches!(stack.push_u8(5), Err(StackError::Overflow)));
+        assert_eq!(stack.size(), 12);
+        stack.pop_u16().unwrap();
+        assert!(matches!(stack.push_u32(6), Err(StackError::Overflow)));
+        stack.push_u16(7).unwrap();
+        assert_eq!(stack.peek_u16().unwrap(), 7);
+    }
+
+    #[test]
+    fn clear_empties_the_stack() {
+        let mut stack = Vec::new();
+        stack.push_u64(1).unwrap();
+        stack.push_u8(2).unwrap();
+        ComputeStack::clear(&mut stack);
+        assert_eq!(stack.size(), 0);
+        assert!(matches!(stack.pop_u8(), Err(StackError::Underflow)));
+        let mut bounded = BoundedStack::new(vec![1, 2, 3], 3);
+        bounded.clear();
+        assert_eq!(bounded.size(), 0);
+        bounded.push_slice(&[4, 5, 6]).unwrap();
+    }
+
+    #[test]
+    fn truncate_to_shrinks_but_never_grows() {
+        let mut stack = Vec::new();
+        stack.push_u32(0x01020304).unwrap();
+        stack.push_u32(0x05060708).unwrap();
+        stack.truncate_to(4).unwrap();
+        assert_eq!(stack.pop_u32().unwrap(), 0x01020304);
+        stack.truncate_to(0).unwrap();
+        assert!(matches!(stack.truncate_to(1), Err(StackError::Underflow)));
+        let mut bounded = BoundedStack::new(vec![1, 2, 3], 8);
+        assert!(matches!(bounded.truncate_to(4), Err(StackError::Underflow)));
+        bounded.truncate_to(3).unwrap();
+        bounded.truncate_to(1).unwrap();
+        assert_eq!(bounded.pop_u8().unwrap(), 1);
+    }
+
+    #[test]
+    fn read_and_write_at_offsets_from_the_bottom() {
+        let mut stack = Vec::new();
+        stack.push_u16(0x0201).unwrap();
+        stack.push_u16(0x0403).unwrap();
+        assert_eq!(stack.read_at(1, 2).unwrap(), &[2, 3]);
+        stack.write_at(1, &[9, 8, 7]).unwrap();
+        assert_eq!(stack.pop_slice(4).unwrap().as_ref(), &[1, 9, 8, 7]);
+        assert!(matches!(stack.read_at(0, 1), Err(StackError::Underflow)));
+        stack.push_u32(0).unwrap();
+        assert!(matches!(stack.read_at(2, 3), Err(StackError::Underflow)));
+        assert!(matches!(stack.read_at(usize::MAX, 2), Err(StackError::Underflow)));
+        assert!(matches!(stack.write_at(3, &[1, 2]), Err(StackError::Underflow)));
+        assert_eq!(stack.pop_u32().unwrap(), 0);
+    }
+
+    #[test]
+    fn stack_errors_display_readably() {
+        assert_eq!(StackError::Underflow.to_string(), "stack underflow");
+        assert_eq!(StackError::Overflow.to_string(), "stack overflow");
+    }
+
+    #[test]
+    fn array_stack_pushes_pops_and_peeks() {
+        let mut stack = ArrayStack::<8>::new();
+        stack.push_u32(0xDEADBEEF).unwrap();
+        stack.push_i16(-2).unwrap();
+        assert_eq!(stack.size(), 6);
+        assert_eq!(stack.peek_i16().unwrap(), -2);
+        assert_eq!(stack.pop_i16().unwrap(), -2);
+        assert_eq!(stack.pop_u32().unwrap(), 0xDEADBEEF);
+        assert_eq!(stack.size(), 0);
+    }
+
+    #[test]
+    fn array_stack_overflows_and_underflows() {
+        let mut stack = ArrayStack::<8>::new();
+        stack.push_u32(1).unwrap();
+        assert!(matches!(stack.push_u64(2), Err(StackError::Overflow)));
+        stack.push_u32(3).unwrap();
+        assert!(matches!(stack.push_u8(4), Err(StackError::Overflow)));
+        assert_eq!(stack.size(), 8);
+        stack.remove_top(6).unwrap();
+        assert!(matches!(stack.pop_u32(), Err(StackError::Underflow)));
+        assert!(matches!(stack.peek_u32(), Err(StackError::Underflow)));
+        assert_eq!(stack.pop_u16().unwrap(), 1);
+        assert!(matches!(stack.pop_u8(), Err(StackError::Underflow)));
+    }
+
+    #[test]
+    fn push_all_and_pop_n_u64() {
+        let mut stack = Vec::new();
+        stack.push_all(&[&1u64.to_le_bytes(), &2u64.to_le_bytes(), &3u64.to_le_bytes()]).unwrap();
+        assert_eq!(stack.pop_n_u64(2).unwrap(), vec![3, 2]);
+        assert!(stack.pop_n_u64(0).unwrap().is_empty());
+        assert!(matches!(stack.pop_n_u64(2), Err(StackError::Underflow)));
+        assert_eq!(stack.pop_u64().unwrap(), 1);
+        assert!(matches!(stack.pop_n_u64(usize::MAX), Err(StackError::Underflow)));
+    }
+
+    #[test]
+    fn push_all_keeps_the_slices_before_an_overflow() {
+        let mut stack = BoundedStack::new(Vec::new(), 12);
+        assert!(matches!(stack.push_all(&[&[1; 8], &[2; 8]]), Err(StackError::Overflow)));
+        assert_eq!(stack.size(), 8);
+    }
+
+
+    /// Every kind of stack, empty
+    fn stacks() -> Vec<Box<dyn ComputeStack>> {
+        vec![
+            Box::new(Vec::new()),
+            Box::new(ArrayStack::<64>::new()),
+            Box::new(BoundedStack::new(Vec::new(), 64)),
+            Box::new(Box::new(Vec::new()) as Box<dyn ComputeStack>)
+        ]
+    }
+
+    #[test]
+    fn pop_into_takes_the_top_bytes() {
+        for mut stack in stacks() {
+            stack.push_slice(&[1, 2, 3, 4, 5]).unwrap();
+            let mut buf = [0; 3];
+            stack.pop_into(&mut buf).unwrap();
+            assert_eq!(buf, [3, 4, 5], "{:?}", stack);
+            assert_eq!(stack.peek_slice(2).unwrap(), &[1, 2]);
+            assert!(matches!(stack.pop_into(&mut buf), Err(StackError::Underflow)));
+            assert_eq!(stack.size(), 2, "{:?}", stack);
+            stack.pop_into(&mut []).unwrap();
+            assert_eq!(stack.size(), 2);
+        }
+    }
+
+    #[test]
+    fn typed_pops_return_what_was_pushed() {
+        for mut stack in stacks() {
+            stack.push_u8(0xAB).unwrap();
+            stack.push_u16(0xABCD).unwrap();
+            stack.push_u32(0xDEAD_BEEF).unwrap();
+            stack.push_u64(u64::MAX - 1).unwrap();
+            stack.push_i8(-2).unwrap();
+            stack.push_i16(-300).unwrap();
+            stack.push_i32(-70_000).unwrap();
+            stack.push_i64(i64::MIN).unwrap();
+            stack.push_f32(-1.5).unwrap();
+            stack.push_f64(f64::MAX).unwrap();
+            assert_eq!(stack.pop_f64().unwrap(), f64::MAX);
+            assert_eq!(stack.pop_f32().unwrap(), -1.5);
+            assert_eq!(stack.pop_i64().unwrap(), i64::MIN);
+            assert_eq!(stack.pop_i32().unwrap(), -70_000);
+            assert_eq!(stack.pop_i16().unwrap(), -300);
+            assert_eq!(stack.pop_i8().unwrap(), -2);
+            assert_eq!(stack.pop_u64().unwrap(), u64::MAX - 1);
+            assert_eq!(stack.pop_u32().unwrap(), 0xDEAD_BEEF);
+            assert_eq!(stack.pop_u16().unwrap(), 0xABCD);
+            assert_eq!(stack.pop_u8().unwrap(), 0xAB);
+            assert!(matches!(stack.pop_u8(), Err(StackError::Underflow)));
+            assert_eq!(stack.size(), 0, "{:?}", stack);
+        }
+    }
+
+    #[test]
+    fn reserving_avoids_reallocation() {
+        let mut stack = Vec::new();
+        ComputeStack::reserve(&mut stack, 64);
+        let (pointer, capacity) = (stack.as_ptr(), stack.capacity());
+        for i in 0..4u8 {
+            stack.push_u8(i).unwrap();
+            stack.push_u16(i as u16).unwrap();
+            stack.push_u32(i as u32).unwrap();
+            stack.push_u64(i as u64).unwrap();
+            stack.push_slice(&[i]).unwrap();
+        }
+        assert_eq!(stack.len(), 64);
+        assert_eq!(stack.as_ptr(), pointer);
+        assert_eq!(stack.capacity(), capacity);
+    }
+
+    #[test]
+    fn bounded_stacks_reserve_at_most_their_capacity() {
+        let mut stack = BoundedStack::new(Vec::new(), 16);
+        stack.push_u64(0).unwrap();
+        stack.reserve(1 << 30);
+        assert!(stack.stack.capacity() < 1 << 30);
+        assert!(stack.stack.capacity() >= 16);
+    }
+}
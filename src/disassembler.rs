@@ -0,0 +1,85 @@
+use std::fmt::Write;
+use std::io::{Read, Seek};
+
+use crate::instruction::{decode_program, decode_program_with_limit};
+use crate::Instruction;
+use crate::machine::MachineError;
+
+/// Renders bytecode as one instruction per line, each prefixed with its byte offset
+///
+/// `PSH_*` payloads are shown as hex of their little endian value, so the mnemonic part of each
+/// line can be fed back into the [assembler](crate::assembler::assemble)
+pub fn disassemble<R: Read + Seek>(reader: &mut R) -> Result<String, MachineError> {
+    Ok(render(decode_program(reader)?))
+}
+
+/// Like [disassemble], but fails with [`DecodeLimitExceeded`](MachineError::DecodeLimitExceeded)
+/// if `reader` holds more than `max_instructions` instructions
+pub fn disassemble_with_limit<R: Read + Seek>(reader: &mut R, max_instructions: usize) -> Result<String, MachineError> {
+    Ok(render(decode_program_with_limit(reader, max_instructions)?))
+}
+
+fn render(program: Vec<(u64, Instruction)>) -> String {
+    let mut out = String::new();
+    for (offset, instruction) in program {
+        writeln!(out, "{:08x}: {}", offset, instruction).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::instruction::Instruction::*;
+    use crate::InstructionReceiver;
+
+    #[test]
+    fn disassembles_the_demo_program() {
+        let mut instructions = Vec::new();
+        instructions
+            .instruct(PSH_8(1.0f64.to_le_bytes()))
+            .instruct(PSH_8(1.0f64.to_le_bytes()))
+            .instruct(ADD_F_8)
+            .instruct(CNV_F8_U8);
+        let disassembly = disassemble(&mut Cursor::new(instructions)).unwrap();
+        assert_eq!(disassembly, "\
+00000000: PSH_8 0x3FF0000000000000
+00000009: PSH_8 0x3FF0000000000000
+00000012: ADD_F_8
+00000013: CNV_F8_U8
+");
+    }
+
+    #[test]
+    fn disassembly_assembles_back_to_the_same_bytecode() {
+        let mut instructions = Vec::new();
+        instructions
+            .instruct(PSH_1([0xFE]))
+            .instruct(PSH_2(0xBEEFu16.to_le_bytes()))
+            .instruct(PSH_4(0xDEADBEEFu32.to_le_bytes()))
+            .instruct(PSH_8(u64::MAX.to_le_bytes()))
+            .instruct(SWAP_8);
+        let disassembly = disassemble(&mut Cursor::new(&instructions)).unwrap();
+        let source: String = disassembly.lines().map(|line| line.split_once(": ").unwrap().1.to_string() + "\n").collect();
+        assert_eq!(assemble(&source).unwrap(), instructions);
+    }
+
+    #[test]
+    fn reports_a_truncated_instruction() {
+        assert!(matches!(disassemble(&mut Cursor::new([3, 0, 0])), Err(MachineError::IncompleteInstruction { opcode: 3, offset: 0 })));
+    }
+
+    #[test]
+    fn disassembly_respects_the_instruction_limit() {
+        let mut instructions = Vec::new();
+        instructions.instruct(POP_1).instruct(POP_2);
+        assert_eq!(disassemble_with_limit(&mut Cursor::new(&instructions), 2).unwrap(), "00000000: POP_1\n00000001: POP_2\n");
+        assert!(matches!(
+            disassemble_with_limit(&mut Cursor::new(&instructions), 1),
+            Err(MachineError::DecodeLimitExceeded)
+        ));
+    }
+}
@@ -8,16 +8,18 @@ macro_rules! impl_unchecked_division {
     ($t: ty) => {
         impl InfallibleDivision for $t {
             fn infallible_div(a: Self, b: Self) -> Self {
+                // wrapping so that MIN / -1 results in MIN, rather than overflowing
                 if b != 0 {
-                    a / b
+                    a.wrapping_div(b)
                 } else {
                     <$t>::MAX
                 }
             }
         
             fn infallible_rem(a: Self, b: Self) -> Self {
+                // wrapping so that MIN % -1 results in 0, rather than overflowing
                 if b != 0 {
-                    a % b
+                    a.wrapping_rem(b)
                 } else {
                     a
                 }
@@ -34,3 +36,35 @@ impl_unchecked_division!{
     i8, i16, i32, i64
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! signed_division_test {
+        ($name: ident, $t: ty) => {
+            #[test]
+            fn $name() {
+                assert_eq!(<$t>::infallible_div(<$t>::MIN, -1), <$t>::MIN);
+                assert_eq!(<$t>::infallible_rem(<$t>::MIN, -1), 0);
+                assert_eq!(<$t>::infallible_div(-7, 0), <$t>::MAX);
+                assert_eq!(<$t>::infallible_rem(-7, 0), -7);
+                assert_eq!(<$t>::infallible_div(-7, 2), -3);
+                assert_eq!(<$t>::infallible_rem(-7, 2), -1);
+            }
+        };
+    }
+
+    signed_division_test!(signed_division_of_i8, i8);
+    signed_division_test!(signed_division_of_i16, i16);
+    signed_division_test!(signed_division_of_i32, i32);
+    signed_division_test!(signed_division_of_i64, i64);
+
+    #[test]
+    fn unsigned_division_by_zero() {
+        assert_eq!(u8::infallible_div(7, 0), u8::MAX);
+        assert_eq!(u64::infallible_div(7, 0), u64::MAX);
+        assert_eq!(u16::infallible_rem(7, 0), 7);
+        assert_eq!(u32::infallible_rem(7, 2), 1);
+    }
+}
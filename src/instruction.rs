@@ -1,12 +1,21 @@
-use std::io;
-use std::io::Read;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::{fmt, io};
+use std::io::{Read, Seek, Write};
+use std::iter::FusedIterator;
+use std::sync::LazyLock;
 
 use crate::InvalidComparisonByte;
 use crate::machine::MachineError;
 
-#[allow(non_camel_case_types)]
+/// An instruction executed by the [Machine](crate::machine::Machine)
+///
+/// instructions taking two operands pop the left hand operand first, so for non-commutative
+/// operations the value pushed last (the top of the stack) is on the left, for example pushing
+/// 2 and then 7 before `SUB_1` results in 7 - 2
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 #[repr(u8)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Instruction {
     PSH_1([u8; 1]),
     PSH_2([u8; 2]),
@@ -79,14 +88,23 @@ pub enum Instruction {
     XOR_2,
     XOR_4,
     XOR_8,
+    /// Pops 1 byte as **`value`**\
+    /// Pops 1 byte as **`count`**\
+    /// Pushes 1 byte corresponding to **`value << count`**
     SHL_1,
     SHL_2,
     SHL_4,
     SHL_8,
+    /// Pops 1 byte as **`value`**\
+    /// Pops 1 byte as **`count`**\
+    /// Pushes 1 byte corresponding to **`value >> count`**, shifting in zeros
     SHR_1,
     SHR_2,
     SHR_4,
     SHR_8,
+    /// Pops 1 byte as **`value`**\
+    /// Pops 1 byte as **`count`**\
+    /// Pushes 1 byte corresponding to **`value >> count`**, shifting in copies of the sign bit
     SAR_1,
     SAR_2,
     SAR_4,
@@ -96,6 +114,9 @@ pub enum Instruction {
     ADD_2,
     ADD_4,
     ADD_8,
+    /// Pops 1 byte as **`a`**\
+    /// Pops 1 byte as **`b`**\
+    /// Pushes 1 byte corresponding to **`a - b`**
     SUB_1,
     SUB_2,
     SUB_4,
@@ -104,10 +125,21 @@ pub enum Instruction {
     MUL_2,
     MUL_4,
     MUL_8,
+    /// Pops 1 byte as **`a`**\
+    /// Pops 1 byte as **`b`**\
+    /// Pushes 1 byte corresponding to the quotient **`a / b`**\
+    /// Pushes 1 byte corresponding to the remainder **`a % b`**, leaving it on top of the quotient\
+    /// if **`b`** is 0 the quotient is the maximum value and the remainder is **`a`**
     DIV_REM_U_1,
     DIV_REM_U_2,
     DIV_REM_U_4,
     DIV_REM_U_8,
+    /// Pops 1 byte as **`a`**\
+    /// Pops 1 byte as **`b`**\
+    /// Pushes 1 byte corresponding to the quotient **`a / b`**, rounded towards zero\
+    /// Pushes 1 byte corresponding to the remainder **`a % b`**, leaving it on top of the quotient\
+    /// if **`b`** is 0 the quotient is the maximum value and the remainder is **`a`**, and the
+    /// minimum value divided by -1 results in the minimum value with a remainder of 0
     DIV_REM_S_1,
     DIV_REM_S_2,
     DIV_REM_S_4,
@@ -115,12 +147,21 @@ pub enum Instruction {
 
     ADD_F_4,
     ADD_F_8,
+    /// Pops 4 bytes as **`a`**\
+    /// Pops 4 bytes as **`b`**\
+    /// Pushes 4 bytes corresponding to **`a - b`**
     SUB_F_4,
     SUB_F_8,
     MUL_F_4,
     MUL_F_8,
+    /// Pops 4 bytes as **`a`**\
+    /// Pops 4 bytes as **`b`**\
+    /// Pushes 4 bytes corresponding to **`a / b`**
     DIV_F_4,
     DIV_F_8,
+    /// Pops 4 bytes as **`a`**\
+    /// Pops 4 bytes as **`b`**\
+    /// Pushes 4 bytes corresponding to the remainder of **`a / b`**, which has the sign of **`a`**
     REM_F_4,
     REM_F_8,
 
@@ -129,6 +170,10 @@ pub enum Instruction {
     CNV_S8_F4,
     CNV_S8_F8,
 
+    /// Pops 4 bytes as **`value`**, a float\
+    /// Pushes 8 bytes corresponding to **`value`** rounded towards zero, which saturates the same
+    /// way as Rust's `as`: NaN becomes 0, and values beyond the integer's range, including the
+    /// infinities, become its minimum or maximum value
     CNV_F4_U8,
     CNV_F8_U8,
     CNV_F4_S8,
@@ -137,184 +182,1133 @@ pub enum Instruction {
     CNV_F4_F8,
     CNV_F8_F4,
 
-    CALL_EXT
+    /// Pops 8 bytes as **`id`**\
+    /// Calls the host function registered under **`id`**, which may freely use the stack and heap,
+    /// and pushes the bytes it returns
+    CALL_EXT,
+
+    /// Pops 1 byte as **`value`**\
+    /// Pushes **`value`** twice
+    DUP_1,
+    DUP_2,
+    DUP_4,
+    DUP_8,
+
+    /// Pops 1 byte as **`a`**\
+    /// Pops 1 byte as **`b`**\
+    /// Pushes **`a`**, then pushes **`b`**
+    SWAP_1,
+    SWAP_2,
+    SWAP_4,
+    SWAP_8,
+
+    /// Pops 8 bytes as **`Object Reference`**\
+    /// Removes the referenced [Object] from the heap right away if this was its last stack
+    /// reference and no other [Object] holds it as a child
+    FREE,
+
+    /// Collects garbage on the heap\
+    /// Pushes 8 bytes corresponding to the number of [Object]s collected
+    GC,
+
+    /// Pops 4 bytes as **`value`**\
+    /// Pushes 4 bytes corresponding to **`-value`**
+    NEG_F_4,
+    NEG_F_8,
+    /// Pops 4 bytes as **`value`**\
+    /// Pushes 4 bytes corresponding to the absolute value of **`value`**
+    ABS_F_4,
+    ABS_F_8,
+
+    /// Pops 1 byte as **`a`**\
+    /// Pops 1 byte as **`b`**\
+    /// Pushes the lesser of **`a`** and **`b`**
+    MIN_U_1,
+    MIN_U_2,
+    MIN_U_4,
+    MIN_U_8,
+    /// Pops 1 byte as **`a`**\
+    /// Pops 1 byte as **`b`**\
+    /// Pushes the greater of **`a`** and **`b`**
+    MAX_U_1,
+    MAX_U_2,
+    MAX_U_4,
+    MAX_U_8,
+    MIN_S_1,
+    MIN_S_2,
+    MIN_S_4,
+    MIN_S_8,
+    MAX_S_1,
+    MAX_S_2,
+    MAX_S_4,
+    MAX_S_8,
+
+    /// Pops 1 byte as **`value`**\
+    /// Pops 1 byte as **`count`**\
+    /// Pushes 1 byte corresponding to **`value`** rotated left by **`count`** bits, modulo the bit width
+    ROL_1,
+    ROL_2,
+    ROL_4,
+    ROL_8,
+    /// Pops 1 byte as **`value`**\
+    /// Pops 1 byte as **`count`**\
+    /// Pushes 1 byte corresponding to **`value`** rotated right by **`count`** bits, modulo the bit width
+    ROR_1,
+    ROR_2,
+    ROR_4,
+    ROR_8,
+
+    /// Pops 1 byte as **`value`**\
+    /// Pushes 1 byte corresponding to the number of leading zero bits in **`value`**, which is the bit width if **`value`** is 0
+    CLZ_1,
+    CLZ_2,
+    CLZ_4,
+    CLZ_8,
+    /// Pops 1 byte as **`value`**\
+    /// Pushes 1 byte corresponding to the number of trailing zero bits in **`value`**, which is the bit width if **`value`** is 0
+    CTZ_1,
+    CTZ_2,
+    CTZ_4,
+    CTZ_8,
+    /// Pops 1 byte as **`value`**\
+    /// Pushes 1 byte corresponding to the number of set bits in **`value`**
+    POPCNT_1,
+    POPCNT_2,
+    POPCNT_4,
+    POPCNT_8,
+
+    /// Pops 8 bytes as **`offset`**\
+    /// Pushes a copy of the 8 bytes starting **`offset`** bytes above the bottom of the stack
+    LOAD_8,
+    /// Pops 8 bytes as **`offset`**\
+    /// Pops 8 bytes as **`value`**\
+    /// Overwrites the 8 bytes starting **`offset`** bytes above the bottom of the stack with **`value`**
+    STORE_8,
+
+    /// Pops 8 bytes as **`address`**\
+    /// Pushes the next instruction address onto the call stack, jump to **`address`**
+    CALL_FRAME,
+    /// Pops an address from the call stack, jump to that address
+    RET_FRAME,
+
+    /// Stops execution, failing the step with [`Halted`](MachineError::Halted)\
+    /// The machine stays on the `HALT`, so every later step halts again
+    HALT,
+
+    /// Pops 8 bytes as **`a`**, a nullable **`Object Reference`**\
+    /// Pops 8 bytes as **`b`**, a nullable **`Object Reference`**\
+    /// Pushes 1 byte corresponding to the [`PossiblyOrdering`] of the references, which is `Equal` only if they refer to the same [Object], and `Unordered` if either is null
+    CMP_REF,
+
+    /// Pops 8 bytes as a nullable **`Object Reference`**\
+    /// Pushes 1 byte, 1 if the reference is null and 0 otherwise
+    IS_NULL,
+
+    /// Pops 8 bytes as **`Object Reference`**\
+    /// Pushes 8 bytes corresponding to the number of child slots of the referenced [Object]
+    OBJ_CHILDREN_LEN,
+    /// Pops 8 bytes as **`Object Reference`**\
+    /// Pushes 8 bytes corresponding to the number of data bytes of the referenced [Object]
+    OBJ_DATA_LEN,
+
+    /// Pops 8 bytes as **`Object Reference`**\
+    /// Pops 8 bytes as **`start`**\
+    /// Pops 8 bytes as **`length`**\
+    /// Pops **`length`** bytes as **`data`**\
+    /// Writes **`data`** to the referenced [Object]'s data, beginning at **`start`**
+    MOV_ST_HP_N,
+
+    /// Pops 8 bytes as destination **`Object Reference`**\
+    /// Pops 8 bytes as **`destination_start`**\
+    /// Pops 8 bytes as source **`Object Reference`**\
+    /// Pops 8 bytes as **`source_start`**\
+    /// Pops 8 bytes as **`length`**\
+    /// Copies **`length`** bytes of the source [Object]'s data beginning at **`source_start`** into the destination [Object]'s data beginning at **`destination_start`**, the ranges may overlap when both are the same [Object]
+    MOV_HP_HP,
+
+    /// Pops 8 bytes as **`Object Reference`**\
+    /// Pops 8 bytes as **`start`**\
+    /// Pops 8 bytes as **`length`**\
+    /// Pops 1 byte as **`value`**\
+    /// Writes **`value`** to each of the **`length`** bytes of the referenced [Object]'s data beginning at **`start`**
+    FILL,
+
+    /// Pops 1 byte as **`value`**\
+    /// Pushes 8 bytes corresponding to **`value`** zero-extended
+    CNV_U1_U8,
+    CNV_U2_U8,
+    CNV_U4_U8,
+    /// Pops 1 byte as **`value`**\
+    /// Pushes 8 bytes corresponding to **`value`** sign-extended
+    CNV_S1_S8,
+    CNV_S2_S8,
+    CNV_S4_S8,
+
+    /// Pops 8 bytes as **`value`**\
+    /// Pushes 1 byte corresponding to the low byte of **`value`**, discarding the rest
+    CNV_U8_U1,
+    CNV_U8_U2,
+    CNV_U8_U4,
+
+    /// Pops 4 bytes as **`value`**\
+    /// Pushes 4 bytes corresponding to the square root of **`value`**, which is NaN if **`value`** is negative
+    SQRT_F_4,
+    SQRT_F_8,
+    /// Pops 4 bytes as **`value`**\
+    /// Pushes 4 bytes corresponding to the sine of **`value`**, in radians
+    SIN_F_4,
+    SIN_F_8,
+    /// Pops 4 bytes as **`value`**\
+    /// Pushes 4 bytes corresponding to the cosine of **`value`**, in radians
+    COS_F_4,
+    COS_F_8,
+
+    /// Pops 4 bytes as **`a`**\
+    /// Pops 4 bytes as **`b`**\
+    /// Pops 4 bytes as **`c`**\
+    /// Pushes 4 bytes corresponding to **`a * b + c`**, rounded once rather than after both the multiplication and the addition
+    FMA_F_4,
+    FMA_F_8,
+
+    /// Pops 4 bytes as **`value`**\
+    /// Pushes 1 byte, 1 if **`value`** is NaN and 0 otherwise
+    IS_NAN_4,
+    IS_NAN_8,
+    /// Pops 4 bytes as **`value`**\
+    /// Pushes 1 byte, 1 if **`value`** is positive or negative infinity and 0 otherwise
+    IS_INF_4,
+    IS_INF_8,
+
+    /// Reads 8 bytes from the instruction stream as **`address`**, like `PSH_8` reads its payload\
+    /// Jumps to **`address`**
+    JMP_IMM([u8; 8]),
+
+    /// Pops 1 byte as **`condition`**\
+    /// Pops 8 bytes as **`address`**\
+    /// Jumps to **`address`** if **`condition`** is not 0
+    JMP_IF,
+    /// Pops 1 byte as **`condition`**\
+    /// Pops 8 bytes as **`address`**\
+    /// Jumps to **`address`** if **`condition`** is 0
+    JMP_IF_NOT,
+
+    /// Pops 8 bytes as **`id`**\
+    /// Pops 8 bytes as **`length`**\
+    /// Calls the argument host function registered under **`id`** with the top **`length`** bytes,
+    /// which are then popped, and pushes the bytes it returns
+    CALL_EXT_N,
+
+    /// Pushes 8 bytes corresponding to the null **`Object Reference`**
+    PSH_NULL
 }
 
 impl Instruction {
-    pub fn from_reader<R: Read>(reader: &mut R) -> Result<Self, MachineError> {
-        let discriminant = {
-            let mut data = [0; 1];
-            reader.read_exact(&mut data).map_err(|e| {
-                match e.kind() {
-                    io::ErrorKind::UnexpectedEof => MachineError::EndOfInstructions,
-                    _ => e.into()
-                }
-            })?;
-            data[0]
-        };
-        match discriminant {
-            0 => {
-                let mut data = [0; 1];
-                reader.read_exact(&mut data).map_err(|e| {
-                    match e.kind() {
-                        io::ErrorKind::UnexpectedEof => MachineError::IncompleteInstruction(0),
-                        _ => e.into()
-                    }
-                })?;
-                Ok(Instruction::PSH_1(data))
-            },
-            1 => {
-                let mut data = [0; 2];
-                reader.read_exact(&mut data).map_err(|e| {
-                    match e.kind() {
-                        io::ErrorKind::UnexpectedEof => MachineError::IncompleteInstruction(1),
-                        _ => e.into()
-                    }
-                })?;
-                Ok(Instruction::PSH_2(data))
-            },
-            2 => {
-                let mut data = [0; 4];
-                reader.read_exact(&mut data).map_err(|e| {
-                    match e.kind() {
-                        io::ErrorKind::UnexpectedEof => MachineError::IncompleteInstruction(2),
-                        _ => e.into()
-                    }
-                })?;
-                Ok(Instruction::PSH_4(data))
-            },
-            3 => {
-                let mut data = [0; 8];
-                reader.read_exact(&mut data).map_err(|e| {
+    /// The number of bytes this instruction occupies once encoded, including its opcode
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Instruction::PSH_1(value) => 1 + value.len(),
+            Instruction::PSH_2(value) => 1 + value.len(),
+            Instruction::PSH_4(value) => 1 + value.len(),
+            Instruction::PSH_8(value) => 1 + value.len(),
+            Instruction::JMP_IMM(address) => 1 + address.len(),
+            _ => 1
+        }
+    }
+
+    /// The name of this instruction, as used by [Display] without the payload of `PSH_*` or
+    /// `JMP_IMM`
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::PSH_1(_) => "PSH_1",
+            Instruction::PSH_2(_) => "PSH_2",
+            Instruction::PSH_4(_) => "PSH_4",
+            Instruction::PSH_8(_) => "PSH_8",
+
+            Instruction::POP_1 => "POP_1",
+            Instruction::POP_2 => "POP_2",
+            Instruction::POP_4 => "POP_4",
+            Instruction::POP_8 => "POP_8",
+
+            Instruction::ALLOC => "ALLOC",
+
+            Instruction::COPY_REF => "COPY_REF",
+            Instruction::SET_CHILD => "SET_CHILD",
+            Instruction::GET_CHILD => "GET_CHILD",
+
+            Instruction::MOV_ST_HP_1 => "MOV_ST_HP_1",
+            Instruction::MOV_ST_HP_2 => "MOV_ST_HP_2",
+            Instruction::MOV_ST_HP_4 => "MOV_ST_HP_4",
+            Instruction::MOV_ST_HP_8 => "MOV_ST_HP_8",
+
+            Instruction::MOV_HP_ST_1 => "MOV_HP_ST_1",
+            Instruction::MOV_HP_ST_2 => "MOV_HP_ST_2",
+            Instruction::MOV_HP_ST_4 => "MOV_HP_ST_4",
+            Instruction::MOV_HP_ST_8 => "MOV_HP_ST_8",
+
+            Instruction::JSR => "JSR",
+            Instruction::RET => "RET",
+
+            Instruction::JMP_EQ => "JMP_EQ",
+            Instruction::JMP_NE => "JMP_NE",
+            Instruction::JMP_GE => "JMP_GE",
+            Instruction::JMP_GT => "JMP_GT",
+            Instruction::JMP_LE => "JMP_LE",
+            Instruction::JMP_LT => "JMP_LT",
+
+            Instruction::CMP_U_1 => "CMP_U_1",
+            Instruction::CMP_U_2 => "CMP_U_2",
+            Instruction::CMP_U_4 => "CMP_U_4",
+            Instruction::CMP_U_8 => "CMP_U_8",
+            Instruction::CMP_S_1 => "CMP_S_1",
+            Instruction::CMP_S_2 => "CMP_S_2",
+            Instruction::CMP_S_4 => "CMP_S_4",
+            Instruction::CMP_S_8 => "CMP_S_8",
+            Instruction::CMP_F4 => "CMP_F4",
+            Instruction::CMP_F8 => "CMP_F8",
+
+            Instruction::NOT_1 => "NOT_1",
+            Instruction::NOT_2 => "NOT_2",
+            Instruction::NOT_4 => "NOT_4",
+            Instruction::NOT_8 => "NOT_8",
+            Instruction::AND_1 => "AND_1",
+            Instruction::AND_2 => "AND_2",
+            Instruction::AND_4 => "AND_4",
+            Instruction::AND_8 => "AND_8",
+            Instruction::OR_1 => "OR_1",
+            Instruction::OR_2 => "OR_2",
+            Instruction::OR_4 => "OR_4",
+            Instruction::OR_8 => "OR_8",
+            Instruction::XOR_1 => "XOR_1",
+            Instruction::XOR_2 => "XOR_2",
+            Instruction::XOR_4 => "XOR_4",
+            Instruction::XOR_8 => "XOR_8",
+            Instruction::SHL_1 => "SHL_1",
+            Instruction::SHL_2 => "SHL_2",
+            Instruction::SHL_4 => "SHL_4",
+            Instruction::SHL_8 => "SHL_8",
+            Instruction::SHR_1 => "SHR_1",
+            Instruction::SHR_2 => "SHR_2",
+            Instruction::SHR_4 => "SHR_4",
+            Instruction::SHR_8 => "SHR_8",
+            Instruction::SAR_1 => "SAR_1",
+            Instruction::SAR_2 => "SAR_2",
+            Instruction::SAR_4 => "SAR_4",
+            Instruction::SAR_8 => "SAR_8",
+
+            Instruction::ADD_1 => "ADD_1",
+            Instruction::ADD_2 => "ADD_2",
+            Instruction::ADD_4 => "ADD_4",
+            Instruction::ADD_8 => "ADD_8",
+            Instruction::SUB_1 => "SUB_1",
+            Instruction::SUB_2 => "SUB_2",
+            Instruction::SUB_4 => "SUB_4",
+            Instruction::SUB_8 => "SUB_8",
+            Instruction::MUL_1 => "MUL_1",
+            Instruction::MUL_2 => "MUL_2",
+            Instruction::MUL_4 => "MUL_4",
+            Instruction::MUL_8 => "MUL_8",
+            Instruction::DIV_REM_U_1 => "DIV_REM_U_1",
+            Instruction::DIV_REM_U_2 => "DIV_REM_U_2",
+            Instruction::DIV_REM_U_4 => "DIV_REM_U_4",
+            Instruction::DIV_REM_U_8 => "DIV_REM_U_8",
+            Instruction::DIV_REM_S_1 => "DIV_REM_S_1",
+            Instruction::DIV_REM_S_2 => "DIV_REM_S_2",
+            Instruction::DIV_REM_S_4 => "DIV_REM_S_4",
+            Instruction::DIV_REM_S_8 => "DIV_REM_S_8",
+
+            Instruction::ADD_F_4 => "ADD_F_4",
+            Instruction::ADD_F_8 => "ADD_F_8",
+            Instruction::SUB_F_4 => "SUB_F_4",
+            Instruction::SUB_F_8 => "SUB_F_8",
+            Instruction::MUL_F_4 => "MUL_F_4",
+            Instruction::MUL_F_8 => "MUL_F_8",
+            Instruction::DIV_F_4 => "DIV_F_4",
+            Instruction::DIV_F_8 => "DIV_F_8",
+            Instruction::REM_F_4 => "REM_F_4",
+            Instruction::REM_F_8 => "REM_F_8",
+
+            Instruction::CNV_U8_F4 => "CNV_U8_F4",
+            Instruction::CNV_U8_F8 => "CNV_U8_F8",
+            Instruction::CNV_S8_F4 => "CNV_S8_F4",
+            Instruction::CNV_S8_F8 => "CNV_S8_F8",
+
+            Instruction::CNV_F4_U8 => "CNV_F4_U8",
+            Instruction::CNV_F8_U8 => "CNV_F8_U8",
+            Instruction::CNV_F4_S8 => "CNV_F4_S8",
+            Instruction::CNV_F8_S8 => "CNV_F8_S8",
+
+            Instruction::CNV_F4_F8 => "CNV_F4_F8",
+            Instruction::CNV_F8_F4 => "CNV_F8_F4",
+
+            Instruction::CALL_EXT => "CALL_EXT",
+
+            Instruction::DUP_1 => "DUP_1",
+            Instruction::DUP_2 => "DUP_2",
+            Instruction::DUP_4 => "DUP_4",
+            Instruction::DUP_8 => "DUP_8",
+
+            Instruction::SWAP_1 => "SWAP_1",
+            Instruction::SWAP_2 => "SWAP_2",
+            Instruction::SWAP_4 => "SWAP_4",
+            Instruction::SWAP_8 => "SWAP_8",
+
+            Instruction::FREE => "FREE",
+
+            Instruction::GC => "GC",
+
+            Instruction::NEG_F_4 => "NEG_F_4",
+            Instruction::NEG_F_8 => "NEG_F_8",
+            Instruction::ABS_F_4 => "ABS_F_4",
+            Instruction::ABS_F_8 => "ABS_F_8",
+
+            Instruction::MIN_U_1 => "MIN_U_1",
+            Instruction::MIN_U_2 => "MIN_U_2",
+            Instruction::MIN_U_4 => "MIN_U_4",
+            Instruction::MIN_U_8 => "MIN_U_8",
+            Instruction::MAX_U_1 => "MAX_U_1",
+            Instruction::MAX_U_2 => "MAX_U_2",
+            Instruction::MAX_U_4 => "MAX_U_4",
+            Instruction::MAX_U_8 => "MAX_U_8",
+            Instruction::MIN_S_1 => "MIN_S_1",
+            Instruction::MIN_S_2 => "MIN_S_2",
+            Instruction::MIN_S_4 => "MIN_S_4",
+            Instruction::MIN_S_8 => "MIN_S_8",
+            Instruction::MAX_S_1 => "MAX_S_1",
+            Instruction::MAX_S_2 => "MAX_S_2",
+            Instruction::MAX_S_4 => "MAX_S_4",
+            Instruction::MAX_S_8 => "MAX_S_8",
+
+            Instruction::ROL_1 => "ROL_1",
+            Instruction::ROL_2 => "ROL_2",
+            Instruction::ROL_4 => "ROL_4",
+            Instruction::ROL_8 => "ROL_8",
+            Instruction::ROR_1 => "ROR_1",
+            Instruction::ROR_2 => "ROR_2",
+            Instruction::ROR_4 => "ROR_4",
+            Instruction::ROR_8 => "ROR_8",
+
+            Instruction::CLZ_1 => "CLZ_1",
+            Instruction::CLZ_2 => "CLZ_2",
+            Instruction::CLZ_4 => "CLZ_4",
+            Instruction::CLZ_8 => "CLZ_8",
+            Instruction::CTZ_1 => "CTZ_1",
+            Instruction::CTZ_2 => "CTZ_2",
+            Instruction::CTZ_4 => "CTZ_4",
+            Instruction::CTZ_8 => "CTZ_8",
+            Instruction::POPCNT_1 => "POPCNT_1",
+            Instruction::POPCNT_2 => "POPCNT_2",
+            Instruction::POPCNT_4 => "POPCNT_4",
+            Instruction::POPCNT_8 => "POPCNT_8",
+
+            Instruction::LOAD_8 => "LOAD_8",
+            Instruction::STORE_8 => "STORE_8",
+
+            Instruction::CALL_FRAME => "CALL_FRAME",
+            Instruction::RET_FRAME => "RET_FRAME",
+
+            Instruction::HALT => "HALT",
+
+            Instruction::CMP_REF => "CMP_REF",
+
+            Instruction::IS_NULL => "IS_NULL",
+
+            Instruction::OBJ_CHILDREN_LEN => "OBJ_CHILDREN_LEN",
+            Instruction::OBJ_DATA_LEN => "OBJ_DATA_LEN",
+
+            Instruction::MOV_ST_HP_N => "MOV_ST_HP_N",
+
+            Instruction::MOV_HP_HP => "MOV_HP_HP",
+
+            Instruction::FILL => "FILL",
+
+            Instruction::CNV_U1_U8 => "CNV_U1_U8",
+            Instruction::CNV_U2_U8 => "CNV_U2_U8",
+            Instruction::CNV_U4_U8 => "CNV_U4_U8",
+            Instruction::CNV_S1_S8 => "CNV_S1_S8",
+            Instruction::CNV_S2_S8 => "CNV_S2_S8",
+            Instruction::CNV_S4_S8 => "CNV_S4_S8",
+
+            Instruction::CNV_U8_U1 => "CNV_U8_U1",
+            Instruction::CNV_U8_U2 => "CNV_U8_U2",
+            Instruction::CNV_U8_U4 => "CNV_U8_U4",
+
+            Instruction::SQRT_F_4 => "SQRT_F_4",
+            Instruction::SQRT_F_8 => "SQRT_F_8",
+            Instruction::SIN_F_4 => "SIN_F_4",
+            Instruction::SIN_F_8 => "SIN_F_8",
+            Instruction::COS_F_4 => "COS_F_4",
+            Instruction::COS_F_8 => "COS_F_8",
+
+            Instruction::FMA_F_4 => "FMA_F_4",
+            Instruction::FMA_F_8 => "FMA_F_8",
+
+            Instruction::IS_NAN_4 => "IS_NAN_4",
+            Instruction::IS_NAN_8 => "IS_NAN_8",
+            Instruction::IS_INF_4 => "IS_INF_4",
+            Instruction::IS_INF_8 => "IS_INF_8",
+
+            Instruction::JMP_IMM(_) => "JMP_IMM",
+
+            Instruction::JMP_IF => "JMP_IF",
+            Instruction::JMP_IF_NOT => "JMP_IF_NOT",
+
+            Instruction::CALL_EXT_N => "CALL_EXT_N",
+
+            Instruction::PSH_NULL => "PSH_NULL"
+        }
+    }
+
+    /// Whether execution may continue somewhere other than the next instruction after this one,
+    /// by jumping, calling, returning or halting
+    pub fn is_branch(&self) -> bool {
+        matches!(
+            self,
+            Instruction::JSR | Instruction::RET | Instruction::CALL_FRAME | Instruction::RET_FRAME |
+            Instruction::JMP_EQ | Instruction::JMP_NE | Instruction::JMP_GE |
+            Instruction::JMP_GT | Instruction::JMP_LE | Instruction::JMP_LT |
+            Instruction::JMP_IMM(_) | Instruction::JMP_IF | Instruction::JMP_IF_NOT | Instruction::HALT
+        )
+    }
+
+    /// The opcode this instruction is encoded with, which does not include the payload of `PSH_*`
+    /// or the address of `JMP_IMM`
+    pub const fn opcode(&self) -> u8 {
+        match self {
+            Instruction::PSH_1(_) => 0,
+            Instruction::PSH_2(_) => 1,
+            Instruction::PSH_4(_) => 2,
+            Instruction::PSH_8(_) => 3,
+
+            Instruction::POP_1 => 4,
+            Instruction::POP_2 => 5,
+            Instruction::POP_4 => 6,
+            Instruction::POP_8 => 7,
+
+            Instruction::ALLOC => 8,
+            Instruction::COPY_REF => 9,
+            Instruction::SET_CHILD => 10,
+            Instruction::GET_CHILD => 11,
+
+            Instruction::MOV_ST_HP_1 => 12,
+            Instruction::MOV_ST_HP_2 => 13,
+            Instruction::MOV_ST_HP_4 => 14,
+            Instruction::MOV_ST_HP_8 => 15,
+
+            Instruction::MOV_HP_ST_1 => 16,
+            Instruction::MOV_HP_ST_2 => 17,
+            Instruction::MOV_HP_ST_4 => 18,
+            Instruction::MOV_HP_ST_8 => 19,
+
+            Instruction::JSR => 20,
+            Instruction::RET => 21,
+
+            Instruction::JMP_EQ => 22,
+            Instruction::JMP_NE => 23,
+            Instruction::JMP_GE => 24,
+            Instruction::JMP_GT => 25,
+            Instruction::JMP_LE => 26,
+            Instruction::JMP_LT => 27,
+
+
+            Instruction::CMP_U_1 => 28,
+            Instruction::CMP_U_2 => 29,
+            Instruction::CMP_U_4 => 30,
+            Instruction::CMP_U_8 => 31,
+            Instruction::CMP_S_1 => 32,
+            Instruction::CMP_S_2 => 33,
+            Instruction::CMP_S_4 => 34,
+            Instruction::CMP_S_8 => 35,
+            Instruction::CMP_F4 => 36,
+            Instruction::CMP_F8 => 37,
+
+            Instruction::NOT_1 => 38,
+            Instruction::NOT_2 => 39,
+            Instruction::NOT_4 => 40,
+            Instruction::NOT_8 => 41,
+            Instruction::AND_1 => 42,
+            Instruction::AND_2 => 43,
+            Instruction::AND_4 => 44,
+            Instruction::AND_8 => 45,
+            Instruction::OR_1 => 46,
+            Instruction::OR_2 => 47,
+            Instruction::OR_4 => 48,
+            Instruction::OR_8 => 49,
+            Instruction::XOR_1 => 50,
+            Instruction::XOR_2 => 51,
+            Instruction::XOR_4 => 52,
+            Instruction::XOR_8 => 53,
+            Instruction::SHL_1 => 54,
+            Instruction::SHL_2 => 55,
+            Instruction::SHL_4 => 56,
+            Instruction::SHL_8 => 57,
+            Instruction::SHR_1 => 58,
+            Instruction::SHR_2 => 59,
+            Instruction::SHR_4 => 60,
+            Instruction::SHR_8 => 61,
+            Instruction::SAR_1 => 62,
+            Instruction::SAR_2 => 63,
+            Instruction::SAR_4 => 64,
+            Instruction::SAR_8 => 65,
+
+            Instruction::ADD_1 => 66,
+            Instruction::ADD_2 => 67,
+            Instruction::ADD_4 => 68,
+            Instruction::ADD_8 => 69,
+            Instruction::SUB_1 => 70,
+            Instruction::SUB_2 => 71,
+            Instruction::SUB_4 => 72,
+            Instruction::SUB_8 => 73,
+            Instruction::MUL_1 => 74,
+            Instruction::MUL_2 => 75,
+            Instruction::MUL_4 => 76,
+            Instruction::MUL_8 => 77,
+            Instruction::DIV_REM_U_1 => 78,
+            Instruction::DIV_REM_U_2 => 79,
+            Instruction::DIV_REM_U_4 => 80,
+            Instruction::DIV_REM_U_8 => 81,
+            Instruction::DIV_REM_S_1 => 82,
+            Instruction::DIV_REM_S_2 => 83,
+            Instruction::DIV_REM_S_4 => 84,
+            Instruction::DIV_REM_S_8 => 85,
+
+            Instruction::ADD_F_4 => 86,
+            Instruction::ADD_F_8 => 87,
+            Instruction::SUB_F_4 => 88,
+            Instruction::SUB_F_8 => 89,
+            Instruction::MUL_F_4 => 90,
+            Instruction::MUL_F_8 => 91,
+            Instruction::DIV_F_4 => 92,
+            Instruction::DIV_F_8 => 93,
+            Instruction::REM_F_4 => 94,
+            Instruction::REM_F_8 => 95,
+
+            Instruction::CNV_U8_F4 => 96,
+            Instruction::CNV_U8_F8 => 97,
+            Instruction::CNV_S8_F4 => 98,
+            Instruction::CNV_S8_F8 => 99,
+
+            Instruction::CNV_F4_U8 => 100,
+            Instruction::CNV_F8_U8 => 101,
+            Instruction::CNV_F4_S8 => 102,
+            Instruction::CNV_F8_S8 => 103,
+
+            Instruction::CNV_F4_F8 => 104,
+            Instruction::CNV_F8_F4 => 105,
+
+            Instruction::CALL_EXT => 106,
+
+            Instruction::DUP_1 => 107,
+            Instruction::DUP_2 => 108,
+            Instruction::DUP_4 => 109,
+            Instruction::DUP_8 => 110,
+
+            Instruction::SWAP_1 => 111,
+            Instruction::SWAP_2 => 112,
+            Instruction::SWAP_4 => 113,
+            Instruction::SWAP_8 => 114,
+
+            Instruction::FREE => 115,
+            Instruction::GC => 116,
+
+            Instruction::NEG_F_4 => 117,
+            Instruction::NEG_F_8 => 118,
+            Instruction::ABS_F_4 => 119,
+            Instruction::ABS_F_8 => 120,
+
+            Instruction::MIN_U_1 => 121,
+            Instruction::MIN_U_2 => 122,
+            Instruction::MIN_U_4 => 123,
+            Instruction::MIN_U_8 => 124,
+            Instruction::MAX_U_1 => 125,
+            Instruction::MAX_U_2 => 126,
+            Instruction::MAX_U_4 => 127,
+            Instruction::MAX_U_8 => 128,
+            Instruction::MIN_S_1 => 129,
+            Instruction::MIN_S_2 => 130,
+            Instruction::MIN_S_4 => 131,
+            Instruction::MIN_S_8 => 132,
+            Instruction::MAX_S_1 => 133,
+            Instruction::MAX_S_2 => 134,
+            Instruction::MAX_S_4 => 135,
+            Instruction::MAX_S_8 => 136,
+
+            Instruction::ROL_1 => 137,
+            Instruction::ROL_2 => 138,
+            Instruction::ROL_4 => 139,
+            Instruction::ROL_8 => 140,
+            Instruction::ROR_1 => 141,
+            Instruction::ROR_2 => 142,
+            Instruction::ROR_4 => 143,
+            Instruction::ROR_8 => 144,
+
+            Instruction::CLZ_1 => 145,
+            Instruction::CLZ_2 => 146,
+            Instruction::CLZ_4 => 147,
+            Instruction::CLZ_8 => 148,
+            Instruction::CTZ_1 => 149,
+            Instruction::CTZ_2 => 150,
+            Instruction::CTZ_4 => 151,
+            Instruction::CTZ_8 => 152,
+            Instruction::POPCNT_1 => 153,
+            Instruction::POPCNT_2 => 154,
+            Instruction::POPCNT_4 => 155,
+            Instruction::POPCNT_8 => 156,
+
+            Instruction::LOAD_8 => 157,
+            Instruction::STORE_8 => 158,
+
+            Instruction::CALL_FRAME => 159,
+            Instruction::RET_FRAME => 160,
+
+            Instruction::HALT => 161,
+
+            Instruction::CMP_REF => 162,
+
+            Instruction::IS_NULL => 163,
+
+            Instruction::OBJ_CHILDREN_LEN => 164,
+            Instruction::OBJ_DATA_LEN => 165,
+
+            Instruction::MOV_ST_HP_N => 166,
+
+            Instruction::MOV_HP_HP => 167,
+
+            Instruction::FILL => 168,
+
+            Instruction::CNV_U1_U8 => 169,
+            Instruction::CNV_U2_U8 => 170,
+            Instruction::CNV_U4_U8 => 171,
+            Instruction::CNV_S1_S8 => 172,
+            Instruction::CNV_S2_S8 => 173,
+            Instruction::CNV_S4_S8 => 174,
+
+            Instruction::CNV_U8_U1 => 175,
+            Instruction::CNV_U8_U2 => 176,
+            Instruction::CNV_U8_U4 => 177,
+
+            Instruction::SQRT_F_4 => 178,
+            Instruction::SQRT_F_8 => 179,
+            Instruction::SIN_F_4 => 180,
+            Instruction::SIN_F_8 => 181,
+            Instruction::COS_F_4 => 182,
+            Instruction::COS_F_8 => 183,
+
+            Instruction::FMA_F_4 => 184,
+            Instruction::FMA_F_8 => 185,
+
+            Instruction::IS_NAN_4 => 186,
+            Instruction::IS_NAN_8 => 187,
+            Instruction::IS_INF_4 => 188,
+            Instruction::IS_INF_8 => 189,
+
+            Instruction::JMP_IMM(_) => 190,
+
+            Instruction::JMP_IF => 191,
+            Instruction::JMP_IF_NOT => 192,
+
+            Instruction::CALL_EXT_N => 193,
+
+            Instruction::PSH_NULL => 194,
+        }
+    }
+
+    /// Writes the encoded form of this instruction to `w`, the inverse of [`from_reader`](Instruction::from_reader)
+    pub fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&[self.opcode()])?;
+        match self {
+            Instruction::PSH_1(value) => w.write_all(value),
+            Instruction::PSH_2(value) => w.write_all(value),
+            Instruction::PSH_4(value) => w.write_all(value),
+            Instruction::PSH_8(value) => w.write_all(value),
+            Instruction::JMP_IMM(address) => w.write_all(address),
+            _ => Ok(())
+        }
+    }
+
+    /// The instruction encoded with `opcode`, or [None] for unknown opcodes and for those of
+    /// `PSH_*` and `JMP_IMM`, as their payload is not known
+    pub const fn from_opcode(opcode: u8) -> Option<Self> {
+        match opcode {
+            4 => Some(Instruction::POP_1),
+            5 => Some(Instruction::POP_2),
+            6 => Some(Instruction::POP_4),
+            7 => Some(Instruction::POP_8),
+
+            8 => Some(Instruction::ALLOC),
+            9 => Some(Instruction::COPY_REF),
+            10 => Some(Instruction::SET_CHILD),
+            11 => Some(Instruction::GET_CHILD),
+
+            12 => Some(Instruction::MOV_ST_HP_1),
+            13 => Some(Instruction::MOV_ST_HP_2),
+            14 => Some(Instruction::MOV_ST_HP_4),
+            15 => Some(Instruction::MOV_ST_HP_8),
+
+            16 => Some(Instruction::MOV_HP_ST_1),
+            17 => Some(Instruction::MOV_HP_ST_2),
+            18 => Some(Instruction::MOV_HP_ST_4),
+            19 => Some(Instruction::MOV_HP_ST_8),
+
+            20 => Some(Instruction::JSR),
+            21 => Some(Instruction::RET),
+
+            22 => Some(Instruction::JMP_EQ),
+            23 => Some(Instruction::JMP_NE),
+            24 => Some(Instruction::JMP_GE),
+            25 => Some(Instruction::JMP_GT),
+            26 => Some(Instruction::JMP_LE),
+            27 => Some(Instruction::JMP_LT),
+
+
+            28 => Some(Instruction::CMP_U_1),
+            29 => Some(Instruction::CMP_U_2),
+            30 => Some(Instruction::CMP_U_4),
+            31 => Some(Instruction::CMP_U_8),
+            32 => Some(Instruction::CMP_S_1),
+            33 => Some(Instruction::CMP_S_2),
+            34 => Some(Instruction::CMP_S_4),
+            35 => Some(Instruction::CMP_S_8),
+            36 => Some(Instruction::CMP_F4),
+            37 => Some(Instruction::CMP_F8),
+
+            38 => Some(Instruction::NOT_1),
+            39 => Some(Instruction::NOT_2),
+            40 => Some(Instruction::NOT_4),
+            41 => Some(Instruction::NOT_8),
+            42 => Some(Instruction::AND_1),
+            43 => Some(Instruction::AND_2),
+            44 => Some(Instruction::AND_4),
+            45 => Some(Instruction::AND_8),
+            46 => Some(Instruction::OR_1),
+            47 => Some(Instruction::OR_2),
+            48 => Some(Instruction::OR_4),
+            49 => Some(Instruction::OR_8),
+            50 => Some(Instruction::XOR_1),
+            51 => Some(Instruction::XOR_2),
+            52 => Some(Instruction::XOR_4),
+            53 => Some(Instruction::XOR_8),
+            54 => Some(Instruction::SHL_1),
+            55 => Some(Instruction::SHL_2),
+            56 => Some(Instruction::SHL_4),
+            57 => Some(Instruction::SHL_8),
+            58 => Some(Instruction::SHR_1),
+            59 => Some(Instruction::SHR_2),
+            60 => Some(Instruction::SHR_4),
+            61 => Some(Instruction::SHR_8),
+            62 => Some(Instruction::SAR_1),
+            63 => Some(Instruction::SAR_2),
+            64 => Some(Instruction::SAR_4),
+            65 => Some(Instruction::SAR_8),
+
+            66 => Some(Instruction::ADD_1),
+            67 => Some(Instruction::ADD_2),
+            68 => Some(Instruction::ADD_4),
+            69 => Some(Instruction::ADD_8),
+            70 => Some(Instruction::SUB_1),
+            71 => Some(Instruction::SUB_2),
+            72 => Some(Instruction::SUB_4),
+            73 => Some(Instruction::SUB_8),
+            74 => Some(Instruction::MUL_1),
+            75 => Some(Instruction::MUL_2),
+            76 => Some(Instruction::MUL_4),
+            77 => Some(Instruction::MUL_8),
+            78 => Some(Instruction::DIV_REM_U_1),
+            79 => Some(Instruction::DIV_REM_U_2),
+            80 => Some(Instruction::DIV_REM_U_4),
+            81 => Some(Instruction::DIV_REM_U_8),
+            82 => Some(Instruction::DIV_REM_S_1),
+            83 => Some(Instruction::DIV_REM_S_2),
+            84 => Some(Instruction::DIV_REM_S_4),
+            85 => Some(Instruction::DIV_REM_S_8),
+
+            86 => Some(Instruction::ADD_F_4),
+            87 => Some(Instruction::ADD_F_8),
+            88 => Some(Instruction::SUB_F_4),
+            89 => Some(Instruction::SUB_F_8),
+            90 => Some(Instruction::MUL_F_4),
+            91 => Some(Instruction::MUL_F_8),
+            92 => Some(Instruction::DIV_F_4),
+            93 => Some(Instruction::DIV_F_8),
+            94 => Some(Instruction::REM_F_4),
+            95 => Some(Instruction::REM_F_8),
+
+            96 => Some(Instruction::CNV_U8_F4),
+            97 => Some(Instruction::CNV_U8_F8),
+            98 => Some(Instruction::CNV_S8_F4),
+            99 => Some(Instruction::CNV_S8_F8),
+
+            100 => Some(Instruction::CNV_F4_U8),
+            101 => Some(Instruction::CNV_F8_U8),
+            102 => Some(Instruction::CNV_F4_S8),
+            103 => Some(Instruction::CNV_F8_S8),
+
+            104 => Some(Instruction::CNV_F4_F8),
+            105 => Some(Instruction::CNV_F8_F4),
+
+            106 => Some(Instruction::CALL_EXT),
+
+            107 => Some(Instruction::DUP_1),
+            108 => Some(Instruction::DUP_2),
+            109 => Some(Instruction::DUP_4),
+            110 => Some(Instruction::DUP_8),
+
+            111 => Some(Instruction::SWAP_1),
+            112 => Some(Instruction::SWAP_2),
+            113 => Some(Instruction::SWAP_4),
+            114 => Some(Instruction::SWAP_8),
+
+            115 => Some(Instruction::FREE),
+            116 => Some(Instruction::GC),
+
+            117 => Some(Instruction::NEG_F_4),
+            118 => Some(Instruction::NEG_F_8),
+            119 => Some(Instruction::ABS_F_4),
+            120 => Some(Instruction::ABS_F_8),
+
+            121 => Some(Instruction::MIN_U_1),
+            122 => Some(Instruction::MIN_U_2),
+            123 => Some(Instruction::MIN_U_4),
+            124 => Some(Instruction::MIN_U_8),
+            125 => Some(Instruction::MAX_U_1),
+            126 => Some(Instruction::MAX_U_2),
+            127 => Some(Instruction::MAX_U_4),
+            128 => Some(Instruction::MAX_U_8),
+            129 => Some(Instruction::MIN_S_1),
+            130 => Some(Instruction::MIN_S_2),
+            131 => Some(Instruction::MIN_S_4),
+            132 => Some(Instruction::MIN_S_8),
+            133 => Some(Instruction::MAX_S_1),
+            134 => Some(Instruction::MAX_S_2),
+            135 => Some(Instruction::MAX_S_4),
+            136 => Some(Instruction::MAX_S_8),
+
+            137 => Some(Instruction::ROL_1),
+            138 => Some(Instruction::ROL_2),
+            139 => Some(Instruction::ROL_4),
+            140 => Some(Instruction::ROL_8),
+            141 => Some(Instruction::ROR_1),
+            142 => Some(Instruction::ROR_2),
+            143 => Some(Instruction::ROR_4),
+            144 => Some(Instruction::ROR_8),
+
+            145 => Some(Instruction::CLZ_1),
+            146 => Some(Instruction::CLZ_2),
+            147 => Some(Instruction::CLZ_4),
+            148 => Some(Instruction::CLZ_8),
+            149 => Some(Instruction::CTZ_1),
+            150 => Some(Instruction::CTZ_2),
+            151 => Some(Instruction::CTZ_4),
+            152 => Some(Instruction::CTZ_8),
+            153 => Some(Instruction::POPCNT_1),
+            154 => Some(Instruction::POPCNT_2),
+            155 => Some(Instruction::POPCNT_4),
+            156 => Some(Instruction::POPCNT_8),
+
+            157 => Some(Instruction::LOAD_8),
+            158 => Some(Instruction::STORE_8),
+
+            159 => Some(Instruction::CALL_FRAME),
+            160 => Some(Instruction::RET_FRAME),
+
+            161 => Some(Instruction::HALT),
+
+            162 => Some(Instruction::CMP_REF),
+
+            163 => Some(Instruction::IS_NULL),
+
+            164 => Some(Instruction::OBJ_CHILDREN_LEN),
+            165 => Some(Instruction::OBJ_DATA_LEN),
+
+            166 => Some(Instruction::MOV_ST_HP_N),
+
+            167 => Some(Instruction::MOV_HP_HP),
+
+            168 => Some(Instruction::FILL),
+
+            169 => Some(Instruction::CNV_U1_U8),
+            170 => Some(Instruction::CNV_U2_U8),
+            171 => Some(Instruction::CNV_U4_U8),
+            172 => Some(Instruction::CNV_S1_S8),
+            173 => Some(Instruction::CNV_S2_S8),
+            174 => Some(Instruction::CNV_S4_S8),
+
+            175 => Some(Instruction::CNV_U8_U1),
+            176 => Some(Instruction::CNV_U8_U2),
+            177 => Some(Instruction::CNV_U8_U4),
+
+            178 => Some(Instruction::SQRT_F_4),
+            179 => Some(Instruction::SQRT_F_8),
+            180 => Some(Instruction::SIN_F_4),
+            181 => Some(Instruction::SIN_F_8),
+            182 => Some(Instruction::COS_F_4),
+            183 => Some(Instruction::COS_F_8),
+
+            184 => Some(Instruction::FMA_F_4),
+            185 => Some(Instruction::FMA_F_8),
+
+            186 => Some(Instruction::IS_NAN_4),
+            187 => Some(Instruction::IS_NAN_8),
+            188 => Some(Instruction::IS_INF_4),
+            189 => Some(Instruction::IS_INF_8),
+
+            191 => Some(Instruction::JMP_IF),
+            192 => Some(Instruction::JMP_IF_NOT),
+
+            193 => Some(Instruction::CALL_EXT_N),
+
+            194 => Some(Instruction::PSH_NULL),
+
+            _ => None
+        }
+    }
+
+    /// The instruction named by `mnemonic`, ignoring ASCII case, with an all zero payload for
+    /// `PSH_*` and `JMP_IMM`
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        MNEMONICS.get(mnemonic.to_ascii_uppercase().as_str()).copied()
+    }
+
+    /// An iterator decoding instructions from `reader` one at a time, see [InstructionIter]
+    pub fn iter_from<R: Read + Seek>(reader: R) -> InstructionIter<R> {
+        InstructionIter {
+            reader,
+            finished: false
+        }
+    }
+
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self, MachineError> {
+        let offset = reader.stream_position()?;
+        let mut opcode = [0; 1];
+        reader.read_exact(&mut opcode).map_err(|e| {
+            match e.kind() {
+                io::ErrorKind::UnexpectedEof => MachineError::EndOfInstructions,
+                _ => e.into()
+            }
+        })?;
+        let opcode = opcode[0];
+        match DECODE_TABLE[opcode as usize] {
+            DecodeKind::Unknown => Err(MachineError::UnknownInstruction { opcode, offset }),
+            DecodeKind::Nullary(instruction) => Ok(instruction),
+            DecodeKind::Payload(length, construct) => {
+                let mut payload = [0; 8];
+                reader.read_exact(&mut payload[..length]).map_err(|e| {
                     match e.kind() {
-                        io::ErrorKind::UnexpectedEof => MachineError::IncompleteInstruction(3),
+                        io::ErrorKind::UnexpectedEof => MachineError::IncompleteInstruction { opcode, offset },
                         _ => e.into()
                     }
                 })?;
-                Ok(Instruction::PSH_8(data))
-            },
-
-            4 => Ok(Instruction::POP_1),
-            5 => Ok(Instruction::POP_2),
-            6 => Ok(Instruction::POP_4),
-            7 => Ok(Instruction::POP_8),
-
-            8 => Ok(Instruction::ALLOC),
-            9 => Ok(Instruction::COPY_REF),
-            10 => Ok(Instruction::SET_CHILD),
-            11 => Ok(Instruction::GET_CHILD),
-
-            12 => Ok(Instruction::MOV_ST_HP_1),
-            13 => Ok(Instruction::MOV_ST_HP_2),
-            14 => Ok(Instruction::MOV_ST_HP_4),
-            15 => Ok(Instruction::MOV_ST_HP_8),
-
-            16 => Ok(Instruction::MOV_HP_ST_1),
-            17 => Ok(Instruction::MOV_HP_ST_2),
-            18 => Ok(Instruction::MOV_HP_ST_4),
-            19 => Ok(Instruction::MOV_HP_ST_8),
-
-            20 => Ok(Instruction::JSR),
-            21 => Ok(Instruction::RET),
-
-            22 => Ok(Instruction::JMP_EQ),
-            23 => Ok(Instruction::JMP_NE),
-            24 => Ok(Instruction::JMP_GE),
-            25 => Ok(Instruction::JMP_GT),
-            26 => Ok(Instruction::JMP_LE),
-            27 => Ok(Instruction::JMP_LT),
-
-
-            28 => Ok(Instruction::CMP_U_1),
-            29 => Ok(Instruction::CMP_U_2),
-            30 => Ok(Instruction::CMP_U_4),
-            31 => Ok(Instruction::CMP_U_8),
-            32 => Ok(Instruction::CMP_S_1),
-            33 => Ok(Instruction::CMP_S_2),
-            34 => Ok(Instruction::CMP_S_4),
-            35 => Ok(Instruction::CMP_S_8),
-            36 => Ok(Instruction::CMP_F4),
-            37 => Ok(Instruction::CMP_F8),
-
-            38 => Ok(Instruction::NOT_1),
-            39 => Ok(Instruction::NOT_2),
-            40 => Ok(Instruction::NOT_4),
-            41 => Ok(Instruction::NOT_8),
-            42 => Ok(Instruction::AND_1),
-            43 => Ok(Instruction::AND_2),
-            44 => Ok(Instruction::AND_4),
-            45 => Ok(Instruction::AND_8),
-            46 => Ok(Instruction::OR_1),
-            47 => Ok(Instruction::OR_2),
-            48 => Ok(Instruction::OR_4),
-            49 => Ok(Instruction::OR_8),
-            50 => Ok(Instruction::XOR_1),
-            51 => Ok(Instruction::XOR_2),
-            52 => Ok(Instruction::XOR_4),
-            53 => Ok(Instruction::XOR_8),
-            54 => Ok(Instruction::SHL_1),
-            55 => Ok(Instruction::SHL_2),
-            56 => Ok(Instruction::SHL_4),
-            57 => Ok(Instruction::SHL_8),
-            58 => Ok(Instruction::SHR_1),
-            59 => Ok(Instruction::SHR_2),
-            60 => Ok(Instruction::SHR_4),
-            61 => Ok(Instruction::SHR_8),
-            62 => Ok(Instruction::SAR_1),
-            63 => Ok(Instruction::SAR_2),
-            64 => Ok(Instruction::SAR_4),
-            65 => Ok(Instruction::SAR_8),
-
-            66 => Ok(Instruction::ADD_1),
-            67 => Ok(Instruction::ADD_2),
-            68 => Ok(Instruction::ADD_4),
-            69 => Ok(Instruction::ADD_8),
-            70 => Ok(Instruction::SUB_1),
-            71 => Ok(Instruction::SUB_2),
-            72 => Ok(Instruction::SUB_4),
-            73 => Ok(Instruction::SUB_8),
-            74 => Ok(Instruction::MUL_1),
-            75 => Ok(Instruction::MUL_2),
-            76 => Ok(Instruction::MUL_4),
-            77 => Ok(Instruction::MUL_8),
-            78 => Ok(Instruction::DIV_REM_U_1),
-            79 => Ok(Instruction::DIV_REM_U_2),
-            80 => Ok(Instruction::DIV_REM_U_4),
-            81 => Ok(Instruction::DIV_REM_U_8),
-            82 => Ok(Instruction::DIV_REM_S_1),
-            83 => Ok(Instruction::DIV_REM_S_2),
-            84 => Ok(Instruction::DIV_REM_S_4),
-            85 => Ok(Instruction::DIV_REM_S_8),
-
-            86 => Ok(Instruction::ADD_F_4),
-            87 => Ok(Instruction::ADD_F_8),
-            88 => Ok(Instruction::SUB_F_4),
-            89 => Ok(Instruction::SUB_F_8),
-            90 => Ok(Instruction::MUL_F_4),
-            91 => Ok(Instruction::MUL_F_8),
-            92 => Ok(Instruction::DIV_F_4),
-            93 => Ok(Instruction::DIV_F_8),
-            94 => Ok(Instruction::REM_F_4),
-            95 => Ok(Instruction::REM_F_8),
-
-            96 => Ok(Instruction::CNV_U8_F4),
-            97 => Ok(Instruction::CNV_U8_F8),
-            98 => Ok(Instruction::CNV_S8_F4),
-            99 => Ok(Instruction::CNV_S8_F8),
-
-            100 => Ok(Instruction::CNV_F4_U8),
-            101 => Ok(Instruction::CNV_F8_U8),
-            102 => Ok(Instruction::CNV_F4_S8),
-            103 => Ok(Instruction::CNV_F8_S8),
-
-            104 => Ok(Instruction::CNV_F4_F8),
-            105 => Ok(Instruction::CNV_F8_F4),
-
-            106 => Ok(Instruction::CALL_EXT),
-
-            n => Err(MachineError::UnknownInstruction(n))
+                Ok(construct(&payload[..length]))
+            }
         }
+    }
+}
+
+/// Every instruction by its [mnemonic](Instruction::mnemonic), with a zeroed payload for those
+/// that have one
+static MNEMONICS: LazyLock<HashMap<&'static str, Instruction>> = LazyLock::new(|| {
+    [
+        Instruction::PSH_1([0; 1]), Instruction::PSH_2([0; 2]), Instruction::PSH_4([0; 4]), Instruction::PSH_8([0; 8]),
+        Instruction::JMP_IMM([0; 8])
+    ]
+        .into_iter()
+        .chain((0..=u8::MAX).filter_map(Instruction::from_opcode))
+        .map(|instruction| (instruction.mnemonic(), instruction))
+        .collect()
+});
+
+/// How [Instruction::from_reader] decodes the instruction with an opcode
+#[derive(Copy, Clone)]
+enum DecodeKind {
+    Unknown,
+    /// the instruction has no payload
+    Nullary(Instruction),
+    /// the instruction is built from a payload of the given length following its opcode
+    Payload(usize, fn(&[u8]) -> Instruction)
+}
 
+/// The [DecodeKind] of every opcode, indexed by opcode
+const DECODE_TABLE: [DecodeKind; 256] = {
+    let mut table = [DecodeKind::Unknown; 256];
+    let mut opcode = 0;
+    while opcode < table.len() {
+        if let Some(instruction) = Instruction::from_opcode(opcode as u8) {
+            table[opcode] = DecodeKind::Nullary(instruction);
+        }
+        opcode += 1;
+    }
+    table[Instruction::PSH_1([0; 1]).opcode() as usize] = DecodeKind::Payload(1, |payload| Instruction::PSH_1(payload.try_into().unwrap()));
+    table[Instruction::PSH_2([0; 2]).opcode() as usize] = DecodeKind::Payload(2, |payload| Instruction::PSH_2(payload.try_into().unwrap()));
+    table[Instruction::PSH_4([0; 4]).opcode() as usize] = DecodeKind::Payload(4, |payload| Instruction::PSH_4(payload.try_into().unwrap()));
+    table[Instruction::PSH_8([0; 8]).opcode() as usize] = DecodeKind::Payload(8, |payload| Instruction::PSH_8(payload.try_into().unwrap()));
+    table[Instruction::JMP_IMM([0; 8]).opcode() as usize] = DecodeKind::Payload(8, |payload| Instruction::JMP_IMM(payload.try_into().unwrap()));
+    table
+};
+
+/// Decodes instructions until the end of `reader`, pairing each with the offset it was read from
+pub fn decode_program<R: Read + Seek>(reader: &mut R) -> Result<Vec<(u64, Instruction)>, MachineError> {
+    decode_program_with_limit(reader, usize::MAX)
+}
+
+/// Like [decode_program], but fails with [`DecodeLimitExceeded`](MachineError::DecodeLimitExceeded)
+/// if `reader` holds more than `max_instructions` instructions, so that a reader which never ends
+/// cannot be decoded forever
+pub fn decode_program_with_limit<R: Read + Seek>(reader: &mut R, max_instructions: usize) -> Result<Vec<(u64, Instruction)>, MachineError> {
+    let mut program = Vec::new();
+    loop {
+        let offset = reader.stream_position()?;
+        match Instruction::from_reader(reader) {
+            Ok(_) if program.len() == max_instructions => return Err(MachineError::DecodeLimitExceeded),
+            Ok(instruction) => program.push((offset, instruction)),
+            Err(MachineError::EndOfInstructions) => return Ok(program),
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+/// Decodes instructions from a reader as they are requested, ending at the end of the reader
+///
+/// errors other than [`EndOfInstructions`](MachineError::EndOfInstructions) are yielded once,
+/// after which the iterator ends
+pub struct InstructionIter<R: Read + Seek> {
+    reader: R,
+    finished: bool
+}
+
+impl<R: Read + Seek> Iterator for InstructionIter<R> {
+    type Item = Result<Instruction, MachineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        match Instruction::from_reader(&mut self.reader) {
+            Ok(instruction) => Some(Ok(instruction)),
+            Err(MachineError::EndOfInstructions) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> FusedIterator for InstructionIter<R> {}
+
+impl Display for Instruction {
+    /// Formats the mnemonic, followed by the little endian value of the payload in hex for `PSH_*`
+    /// and `JMP_IMM`
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::PSH_1(value) => write!(f, "PSH_1 {:#04X}", u8::from_le_bytes(*value)),
+            Instruction::PSH_2(value) => write!(f, "PSH_2 {:#06X}", u16::from_le_bytes(*value)),
+            Instruction::PSH_4(value) => write!(f, "PSH_4 {:#010X}", u32::from_le_bytes(*value)),
+            Instruction::PSH_8(value) => write!(f, "PSH_8 {:#018X}", u64::from_le_bytes(*value)),
+            Instruction::JMP_IMM(address) => write!(f, "JMP_IMM {:#018X}", u64::from_le_bytes(*address)),
+            instruction => write!(f, "{}", instruction.mnemonic())
+        }
     }
 }
 
@@ -329,3 +1323,269 @@ impl From<InvalidComparisonByte> for InvalidInstruction {
         InvalidInstruction::InvalidComparisonByte(e.0)
     }
 }
+
+/// One instruction of every variant, with distinct payloads for those which have one
+#[cfg(test)]
+pub(crate) fn samples() -> Vec<Instruction> {
+    let mut samples = vec![
+        Instruction::PSH_1([0xA1]),
+        Instruction::PSH_2([0xB1, 0xB2]),
+        Instruction::PSH_4([0xC1, 0xC2, 0xC3, 0xC4]),
+        Instruction::PSH_8([0xD1, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8]),
+        Instruction::JMP_IMM([0xE1, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8])
+    ];
+    samples.extend((0..=u8::MAX).filter_map(Instruction::from_opcode));
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use super::Instruction::*;
+    use crate::InstructionReceiver;
+
+    #[test]
+    fn encoded_len_counts_the_opcode_and_payload() {
+        assert_eq!(PSH_8([0; 8]).encoded_len(), 9);
+        assert_eq!(PSH_4([0; 4]).encoded_len(), 5);
+        assert_eq!(PSH_2([0; 2]).encoded_len(), 3);
+        assert_eq!(PSH_1([0; 1]).encoded_len(), 2);
+        assert_eq!(ADD_8.encoded_len(), 1);
+    }
+
+    #[test]
+    fn encode_is_the_inverse_of_from_reader() {
+        for opcode in 0..=u8::MAX {
+            // the payload, for those instructions which have one, is read from the bytes after the opcode
+            let bytecode = [opcode, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7, 0xA8];
+            let Ok(instruction) = Instruction::from_reader(&mut Cursor::new(bytecode)) else {
+                continue;
+            };
+            let mut encoded = Vec::new();
+            instruction.encode(&mut encoded).unwrap();
+            assert_eq!(encoded, bytecode[..instruction.encoded_len()], "{:?}", instruction);
+        }
+    }
+
+    #[test]
+    fn samples_cover_every_opcode_once() {
+        let mut opcodes: Vec<u8> = samples().iter().map(Instruction::opcode).collect();
+        opcodes.sort_unstable();
+        // a variant left out of from_opcode, or an opcode shared by two variants, leaves a gap here
+        let expected: Vec<u8> = (0..opcodes.len()).map(|opcode| opcode as u8).collect();
+        assert_eq!(opcodes, expected);
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        for instruction in samples() {
+            let mut bytecode = Vec::new();
+            bytecode.instruct(instruction);
+            assert_eq!(bytecode.len(), instruction.encoded_len(), "{:?}", instruction);
+            assert_eq!(bytecode[0], instruction.opcode(), "{:?}", instruction);
+            let mut reader = Cursor::new(bytecode);
+            assert_eq!(Instruction::from_reader(&mut reader).unwrap(), instruction);
+            assert_eq!(reader.position() as usize, instruction.encoded_len(), "{:?}", instruction);
+        }
+    }
+
+    #[test]
+    fn display_formats_mnemonics_and_payloads() {
+        assert_eq!(PSH_8(1.0f64.to_le_bytes()).to_string(), "PSH_8 0x3FF0000000000000");
+        assert_eq!(PSH_1([0xA]).to_string(), "PSH_1 0x0A");
+        assert_eq!(PSH_2(0xBEEFu16.to_le_bytes()).to_string(), "PSH_2 0xBEEF");
+        assert_eq!(PSH_4(1u32.to_le_bytes()).to_string(), "PSH_4 0x00000001");
+        assert_eq!(JMP_IMM(16u64.to_le_bytes()).to_string(), "JMP_IMM 0x0000000000000010");
+        assert_eq!(POP_2.to_string(), "POP_2");
+        assert_eq!(ADD_F_8.to_string(), "ADD_F_8");
+    }
+
+    #[test]
+    fn mnemonics_match_the_variant_names() {
+        for instruction in samples() {
+            let debug = format!("{:?}", instruction);
+            assert_eq!(instruction.mnemonic(), debug.split('(').next().unwrap());
+        }
+    }
+
+    #[test]
+    fn decoded_streams_compare_equal() {
+        let mut bytecode = Vec::new();
+        bytecode.instruct(PSH_2([1, 2])).instruct(SWAP_2).instruct(GC).instruct(PSH_2([1, 2]));
+        let mut reader = Cursor::new(bytecode);
+        let mut decoded = Vec::new();
+        while let Ok(instruction) = Instruction::from_reader(&mut reader) {
+            decoded.push(instruction);
+        }
+        assert_eq!(decoded, vec![PSH_2([1, 2]), SWAP_2, GC, PSH_2([1, 2])]);
+        assert_ne!(decoded[0], PSH_2([2, 1]));
+        let distinct: std::collections::HashSet<Instruction> = decoded.into_iter().collect();
+        assert_eq!(distinct.len(), 3);
+    }
+
+    #[test]
+    fn from_opcode_agrees_with_opcode() {
+        assert_eq!(ADD_8.opcode(), 69);
+        assert_eq!(PSH_8([0xFF; 8]).opcode(), 3);
+        assert_eq!(Instruction::from_opcode(8), Some(ALLOC));
+        assert_eq!(Instruction::from_opcode(0), None);
+        assert_eq!(Instruction::from_opcode(u8::MAX), None);
+        for instruction in samples() {
+            let expected = match instruction {
+                // variants with a payload cannot be built from the opcode alone
+                PSH_1(_) | PSH_2(_) | PSH_4(_) | PSH_8(_) | JMP_IMM(_) => None,
+                _ => Some(instruction)
+            };
+            assert_eq!(Instruction::from_opcode(instruction.opcode()), expected, "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn from_mnemonic_finds_every_instruction() {
+        for instruction in samples() {
+            let found = Instruction::from_mnemonic(&instruction.mnemonic().to_ascii_lowercase()).unwrap();
+            assert_eq!(found.opcode(), instruction.opcode());
+        }
+        assert_eq!(Instruction::from_mnemonic("Add_F_8"), Some(ADD_F_8));
+        assert_eq!(Instruction::from_mnemonic("PSH_8"), Some(PSH_8([0; 8])));
+        assert_eq!(Instruction::from_mnemonic("ADD_F_16"), None);
+    }
+
+    #[test]
+    fn decode_program_pairs_instructions_with_offsets() {
+        let mut bytecode = Vec::new();
+        bytecode
+            .instruct(PSH_8(1.0f64.to_le_bytes()))
+            .instruct(PSH_8(1.0f64.to_le_bytes()))
+            .instruct(ADD_F_8)
+            .instruct(CNV_F8_U8);
+        let program = decode_program(&mut Cursor::new(bytecode)).unwrap();
+        assert_eq!(program, vec![
+            (0, PSH_8(1.0f64.to_le_bytes())),
+            (9, PSH_8(1.0f64.to_le_bytes())),
+            (18, ADD_F_8),
+            (19, CNV_F8_U8)
+        ]);
+        assert_eq!(decode_program(&mut Cursor::new([])).unwrap(), vec![]);
+        assert!(matches!(decode_program(&mut Cursor::new([87, 1, 0])), Err(MachineError::IncompleteInstruction { opcode: 1, offset: 1 })));
+    }
+
+    #[test]
+    fn undecodable_instructions_report_their_offset() {
+        let mut program = Vec::new();
+        program.instruct(POP_1).instruct(PSH_8([0xFF; 8]));
+        program.truncate(program.len() - 1);
+        let mut reader = Cursor::new(program);
+        assert_eq!(Instruction::from_reader(&mut reader).unwrap(), POP_1);
+        assert!(matches!(
+            Instruction::from_reader(&mut reader),
+            Err(MachineError::IncompleteInstruction { opcode: 3, offset: 1 })
+        ));
+
+        let mut program = Vec::new();
+        program.instruct(POP_1).instruct(POP_2).push(u8::MAX);
+        let mut reader = Cursor::new(program);
+        Instruction::from_reader(&mut reader).unwrap();
+        Instruction::from_reader(&mut reader).unwrap();
+        assert!(matches!(
+            Instruction::from_reader(&mut reader),
+            Err(MachineError::UnknownInstruction { opcode: u8::MAX, offset: 2 })
+        ));
+    }
+
+    /// A reader of `POP_1` instructions which never ends
+    struct Repeating(u64);
+
+    impl Read for Repeating {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            buf.fill(POP_1.opcode());
+            self.0 += buf.len() as u64;
+            Ok(buf.len())
+        }
+    }
+
+    impl Seek for Repeating {
+        fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+            match pos {
+                io::SeekFrom::Current(0) => Ok(self.0),
+                _ => unimplemented!()
+            }
+        }
+    }
+
+    #[test]
+    fn decode_limit_stops_an_endless_reader() {
+        assert!(matches!(decode_program_with_limit(&mut Repeating(0), 100), Err(MachineError::DecodeLimitExceeded)));
+        let mut program = Vec::new();
+        program.instruct(POP_1).instruct(POP_2);
+        assert_eq!(decode_program_with_limit(&mut Cursor::new(&program), 2).unwrap().len(), 2);
+        assert!(matches!(
+            decode_program_with_limit(&mut Cursor::new(&program), 1),
+            Err(MachineError::DecodeLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn iterates_over_decoded_instructions() {
+        let mut program = Vec::new();
+        for instruction in samples() {
+            program.instruct(instruction);
+        }
+        assert_eq!(Instruction::iter_from(Cursor::new(&program)).count(), samples().len());
+        assert!(Instruction::iter_from(Cursor::new(&program)).map(Result::unwrap).eq(samples()));
+
+        let mut iter = Instruction::iter_from(Cursor::new([POP_1.opcode(), u8::MAX, POP_1.opcode()]));
+        assert_eq!(iter.next().unwrap().unwrap(), POP_1);
+        assert!(matches!(iter.next(), Some(Err(MachineError::UnknownInstruction { opcode: u8::MAX, offset: 1 }))));
+        assert!(iter.next().is_none());
+    }
+
+    /// The decoder [DECODE_TABLE] replaced, matching on the opcode with a branch per payload
+    fn decode_by_match<R: Read + Seek>(reader: &mut R) -> Result<Instruction, MachineError> {
+        let offset = reader.stream_position()?;
+        let mut opcode = [0; 1];
+        reader.read_exact(&mut opcode).map_err(|e| {
+            match e.kind() {
+                io::ErrorKind::UnexpectedEof => MachineError::EndOfInstructions,
+                _ => e.into()
+            }
+        })?;
+        let opcode = opcode[0];
+        let mut payload = |length| {
+            let mut data = [0; 8];
+            reader.read_exact(&mut data[..length]).map_err(|e| {
+                match e.kind() {
+                    io::ErrorKind::UnexpectedEof => MachineError::IncompleteInstruction { opcode, offset },
+                    _ => e.into()
+                }
+            })?;
+            Ok::<_, MachineError>(data)
+        };
+        match opcode {
+            0 => Ok(Instruction::PSH_1(payload(1)?[..1].try_into().unwrap())),
+            1 => Ok(Instruction::PSH_2(payload(2)?[..2].try_into().unwrap())),
+            2 => Ok(Instruction::PSH_4(payload(4)?[..4].try_into().unwrap())),
+            3 => Ok(Instruction::PSH_8(payload(8)?)),
+            190 => Ok(Instruction::JMP_IMM(payload(8)?)),
+            n => Instruction::from_opcode(n).ok_or(MachineError::UnknownInstruction { opcode: n, offset })
+        }
+    }
+
+    #[test]
+    fn table_decoding_matches_match_decoding() {
+        for opcode in 0..=u8::MAX {
+            // a full payload, then every truncation of it
+            let bytes = [opcode, 1, 2, 3, 4, 5, 6, 7, 8];
+            for end in 1..=bytes.len() {
+                let mut table = Cursor::new(&bytes[..end]);
+                let mut matched = Cursor::new(&bytes[..end]);
+                let decoded = Instruction::from_reader(&mut table);
+                let expected = decode_by_match(&mut matched);
+                assert_eq!(format!("{:?}", decoded), format!("{:?}", expected), "opcode {} of {} bytes", opcode, end);
+                assert_eq!(table.position(), matched.position(), "opcode {} of {} bytes", opcode, end);
+            }
+        }
+    }
+}
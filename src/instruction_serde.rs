@@ -0,0 +1,73 @@
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Instruction;
+
+/// The human readable form of an [Instruction], such as `{"op":"PSH_8","bytes":[0,0,0,0,0,0,240,63]}`,
+/// where `bytes` is only present for `PSH_*` and `JMP_IMM`
+#[derive(Serialize, Deserialize)]
+struct InstructionRepr {
+    op: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    bytes: Vec<u8>
+}
+
+impl Serialize for Instruction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = match self {
+            Instruction::PSH_1(value) => value.to_vec(),
+            Instruction::PSH_2(value) => value.to_vec(),
+            Instruction::PSH_4(value) => value.to_vec(),
+            Instruction::PSH_8(value) => value.to_vec(),
+            Instruction::JMP_IMM(address) => address.to_vec(),
+            _ => Vec::new()
+        };
+        InstructionRepr { op: self.mnemonic().to_string(), bytes }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Instruction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = InstructionRepr::deserialize(deserializer)?;
+        let instruction = Instruction::from_mnemonic(&repr.op)
+            .ok_or_else(|| D::Error::custom(format!("unknown instruction {}", repr.op)))?;
+        let bytes = repr.bytes.as_slice();
+        let invalid_length = || D::Error::invalid_length(bytes.len(), &"as many bytes as the payload width");
+        Ok(match instruction {
+            Instruction::PSH_1(_) => Instruction::PSH_1(bytes.try_into().map_err(|_| invalid_length())?),
+            Instruction::PSH_2(_) => Instruction::PSH_2(bytes.try_into().map_err(|_| invalid_length())?),
+            Instruction::PSH_4(_) => Instruction::PSH_4(bytes.try_into().map_err(|_| invalid_length())?),
+            Instruction::PSH_8(_) => Instruction::PSH_8(bytes.try_into().map_err(|_| invalid_length())?),
+            Instruction::JMP_IMM(_) => Instruction::JMP_IMM(bytes.try_into().map_err(|_| invalid_length())?),
+            _ if !bytes.is_empty() => return Err(D::Error::invalid_length(bytes.len(), &"no bytes")),
+            instruction => instruction
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instruction::samples;
+    use crate::Instruction;
+
+    #[test]
+    fn every_instruction_round_trips_through_json() {
+        let program = samples();
+        let json = serde_json::to_string(&program).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<Instruction>>(&json).unwrap(), program);
+    }
+
+    #[test]
+    fn serializes_as_op_and_bytes() {
+        let json = serde_json::to_string(&[Instruction::PSH_8(1.0f64.to_le_bytes()), Instruction::ADD_F_8]).unwrap();
+        assert_eq!(json, r#"[{"op":"PSH_8","bytes":[0,0,0,0,0,0,240,63]},{"op":"ADD_F_8"}]"#);
+    }
+
+    #[test]
+    fn rejects_unknown_ops_and_mismatched_bytes() {
+        assert!(serde_json::from_str::<Instruction>(r#"{"op":"ADD_F_16"}"#).is_err());
+        assert!(serde_json::from_str::<Instruction>(r#"{"op":"PSH_2","bytes":[1]}"#).is_err());
+        assert!(serde_json::from_str::<Instruction>(r#"{"op":"POP_1","bytes":[1]}"#).is_err());
+        assert_eq!(serde_json::from_str::<Instruction>(r#"{"op":"pop_1"}"#).unwrap(), Instruction::POP_1);
+    }
+}
@@ -0,0 +1,108 @@
+use std::io::{Read, Seek};
+
+use instruction::{Instruction, InvalidInstruction};
+
+use crate::compute_heap::{Heap, HeapError, ObjectReference};
+use crate::compute_stack::{ComputeStack, StackError};
+use crate::possibly_ordering::InvalidComparisonByte;
+
+mod infallible_division;
+pub mod compute_stack;
+pub mod possibly_ordering;
+pub mod compute_heap;
+pub mod instruction;
+pub mod machine;
+pub mod assembler;
+pub mod disassembler;
+pub mod validation;
+pub mod byte_code;
+pub mod stdlib;
+pub mod program_file;
+#[cfg(feature = "serde")]
+mod instruction_serde;
+
+pub trait InstructionReceiver {
+    fn instruct(&mut self, instruction: Instruction) -> &mut Self;
+
+    fn push_jump_marker(&mut self, location: &mut usize) -> &mut Self;
+
+    fn assign_jump_marker(&mut self, location: usize, address: u64) -> &mut Self;
+
+    fn assign_jump_marker_here(&mut self, location: usize) -> &mut Self;
+}
+
+impl InstructionReceiver for Vec<u8> {
+    fn instruct(&mut self, instruction: Instruction) -> &mut Self {
+        instruction.encode(self).expect("writing to a Vec<u8> cannot fail");
+        self
+    }
+
+    fn push_jump_marker(&mut self, location: &mut usize) -> &mut Self {
+        // the address is the payload of the PSH_8, so it sits at the end of the encoded instruction
+        self.instruct(Instruction::PSH_8([0; 8]));
+        *location = self.len() - 8;
+        self.instruct(Instruction::JSR);
+        self
+    }
+
+    fn assign_jump_marker(&mut self, location: usize, address: u64) -> &mut Self {
+        self[location..location + 8].copy_from_slice(&address.to_le_bytes());
+        self
+    }
+
+    fn assign_jump_marker_here(&mut self, location: usize) -> &mut Self {
+        let len = self.len();
+        self.assign_jump_marker(location, len as u64)
+    }
+}
+
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::instruction::Instruction::*;
+    use crate::machine::{Machine, MachineError};
+
+    #[test]
+    fn jump_markers_decode_as_a_pushed_address_and_jsr() {
+        let mut program = Vec::new();
+        let mut location = 0;
+        program.push_jump_marker(&mut location);
+        program.instruct(POP_1);
+        program.assign_jump_marker_here(location);
+        let address = program.len() as u64;
+        program.instruct(POP_2);
+
+        let length = program.len() as u64;
+        let mut reader = Cursor::new(program);
+        assert!(matches!(Instruction::from_reader(&mut reader).unwrap(), PSH_8(bytes) if bytes == address.to_le_bytes()));
+        assert!(matches!(Instruction::from_reader(&mut reader).unwrap(), JSR));
+        assert!(matches!(Instruction::from_reader(&mut reader).unwrap(), POP_1));
+        assert!(matches!(Instruction::from_reader(&mut reader).unwrap(), POP_2));
+        assert_eq!(reader.position(), length);
+    }
+
+    #[test]
+    fn jump_markers_jump_to_their_assigned_address() {
+        let mut program = Vec::new();
+        let mut location = 0;
+        program
+            .push_jump_marker(&mut location)
+            .instruct(PSH_1([1]))
+            .assign_jump_marker_here(location)
+            .instruct(PSH_1([2]));
+        let mut machine = Machine::new(Box::new(Cursor::new(program)));
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        assert!(matches!(machine.step(), Err((MachineError::EndOfInstructions, None))));
+        // only the instruction at the assigned address ran, above the return address pushed by JSR
+        assert_eq!(machine.stack.pop_u8().unwrap(), 2);
+        assert_eq!(machine.stack.size(), 8);
+    }
+}
@@ -1,9 +1,18 @@
-use std::io;
-use std::io::{Seek, SeekFrom, Write};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::{fmt, io};
+use std::io::{Cursor, SeekFrom, Write};
+use std::ops::Neg;
+use std::path::Path;
+use std::sync::Arc;
 
 use crate::{ComputeStack, Heap, HeapError, Instruction, InvalidComparisonByte, InvalidInstruction, ObjectReference, ReadSeek, StackError};
+use crate::byte_code::ByteCode;
+use crate::compute_heap::NULL_REFERENCE;
 use crate::infallible_division::InfallibleDivision;
 use crate::possibly_ordering::PossiblyOrdering;
+use crate::program_file::{read_and_validate_header, verify_checksum, IntegrityError, HEADER_LEN};
 
 macro_rules! stack_pop {
     ($self: ident, u8) => {
@@ -75,9 +84,10 @@ macro_rules! mov_st_hp_instruction_impl {
     ($self: ident, $len: expr) => {
         let obj_ref = $self.stack_pop_object_reference()?;
         let start = $self.stack.pop_u64()? as usize;
-        let data = $self.stack.pop_slice($len)?;
+        let mut data = [0; $len];
+        $self.stack.pop_into(&mut data)?;
         let mut out = $self.heap.get_mut_data_slice(&obj_ref, start, $len)?;
-        out.write(&*data)?;
+        out.write_all(&data)?;
     };
 }
 
@@ -90,6 +100,29 @@ macro_rules! mov_hp_st_instruction_impl {
     };
 }
 
+macro_rules! dup_instruction_impl {
+    ($self: ident, $len: expr) => {
+        let mut value = [0; $len];
+        value.copy_from_slice($self.stack.peek_slice($len)?);
+        $self.stack.push_slice(&value)?;
+    };
+}
+
+macro_rules! swap_instruction_impl {
+    ($self: ident, $len: expr) => {
+        // checked up front, so an underflow leaves the stack as it was
+        if $self.stack.size() < $len * 2 {
+            return Err(MachineError::Stack(StackError::Underflow));
+        }
+        let mut a = [0; $len];
+        $self.stack.pop_into(&mut a)?;
+        let mut b = [0; $len];
+        $self.stack.pop_into(&mut b)?;
+        $self.stack.push_slice(&a)?;
+        $self.stack.push_slice(&b)?;
+    };
+}
+
 macro_rules! jump_instruction_impl {
     ($self: ident, $fun: ident) => {
         let cmp: PossiblyOrdering = $self.stack.pop_u8()?.try_into()?;
@@ -107,6 +140,12 @@ macro_rules! compare_instruction_impl {
         let cmp: PossiblyOrdering = PartialOrd::partial_cmp(&a, &b).into();
         $self.stack.push_u8(cmp as u8)?;
     };
+    ($self: ident, $typ: tt, .$method: ident) => {
+        let a = stack_pop!($self, $typ);
+        let b = stack_pop!($self, $typ);
+        let cmp: PossiblyOrdering = a.$method(&b).into();
+        $self.stack.push_u8(cmp as u8)?;
+    };
 }
 
 macro_rules! not_instruction_impl {
@@ -116,7 +155,33 @@ macro_rules! not_instruction_impl {
     };
 }
 
+macro_rules! one_argument_float_instruction_impl {
+    ($self: ident, $typ: tt, $method: ident) => {
+        let value = stack_pop!($self, $typ);
+        stack_push!($self, $typ, value.$method());
+    };
+}
+
+macro_rules! count_instruction_impl {
+    ($self: ident, $typ: tt, $method: ident) => {
+        let value = stack_pop!($self, $typ);
+        stack_push!($self, $typ, value.$method() as $typ);
+    };
+}
+
+macro_rules! predicate_instruction_impl {
+    ($self: ident, $typ: tt, $method: ident) => {
+        let value = stack_pop!($self, $typ);
+        $self.stack.push_u8(value.$method() as u8)?;
+    };
+}
+
 macro_rules! two_argument_instruction_impl {
+    ($self: ident, $typ: tt, .$method: ident) => {
+        let a = stack_pop!($self, $typ);
+        let b = stack_pop!($self, $typ);
+        stack_push!($self, $typ, a.$method(b));
+    };
     ($self: ident, $typ: tt, $op: tt) => {
         two_argument_instruction_impl!($self, $typ, $op, $typ);
     };
@@ -130,12 +195,51 @@ macro_rules! two_argument_instruction_impl {
     };
 }
 
+macro_rules! three_argument_instruction_impl {
+    ($self: ident, $typ: tt, .$method: ident) => {
+        let a = stack_pop!($self, $typ);
+        let b = stack_pop!($self, $typ);
+        let c = stack_pop!($self, $typ);
+        stack_push!($self, $typ, a.$method(b, c));
+    };
+}
+
+macro_rules! integer_arithmetic_instruction_impl {
+    ($self: ident, $instruction: ident, $typ: tt, +) => {
+        integer_arithmetic_instruction_impl!($self, $instruction, $typ, wrapping_add, checked_add, saturating_add);
+    };
+    ($self: ident, $instruction: ident, $typ: tt, -) => {
+        integer_arithmetic_instruction_impl!($self, $instruction, $typ, wrapping_sub, checked_sub, saturating_sub);
+    };
+    ($self: ident, $instruction: ident, $typ: tt, *) => {
+        integer_arithmetic_instruction_impl!($self, $instruction, $typ, wrapping_mul, checked_mul, saturating_mul);
+    };
+    ($self: ident, $instruction: ident, $typ: tt, $wrapping: ident, $checked: ident, $saturating: ident) => {
+        let a = stack_pop!($self, $typ);
+        let b = stack_pop!($self, $typ);
+        let result = match $self.arithmetic_mode {
+            ArithmeticMode::Wrapping => a.$wrapping(b),
+            ArithmeticMode::Checked => a.$checked(b).ok_or(MachineError::ArithmeticOverflow($instruction))?,
+            ArithmeticMode::Saturating => a.$saturating(b)
+        };
+        stack_push!($self, $typ, result);
+    };
+}
+
 macro_rules! shift_instruction_impl {
     ($self: ident, $typ: tt, $op: tt) => {
         two_argument_instruction_impl!($self, $typ $op u8 = $typ);
     }
 }
 
+macro_rules! rotate_instruction_impl {
+    ($self: ident, $typ: tt, $method: ident) => {
+        let value = stack_pop!($self, $typ);
+        let count = $self.stack.pop_u8()? as u32 % <$typ>::BITS;
+        stack_push!($self, $typ, value.$method(count));
+    };
+}
+
 macro_rules! convert_instruction_impl {
     ($self: ident, $from: tt -> $to: tt) => {
         let v = stack_pop!($self, $from);
@@ -155,12 +259,79 @@ macro_rules! div_rem_instruction_impl {
 #[derive(Debug)]
 pub enum MachineError {
     IO(io::Error),
-    UnknownInstruction(u8),
+    /// The opcode read at `offset` in the instructions does not name an instruction
+    UnknownInstruction { opcode: u8, offset: u64 },
     InvalidInstruction(InvalidInstruction),
     EndOfInstructions,
-    IncompleteInstruction(u8),
+    /// The instructions ended partway through the payload of the instruction whose opcode was read
+    /// at `offset`
+    IncompleteInstruction { opcode: u8, offset: u64 },
     Stack(StackError),
-    Heap(HeapError)
+    Heap(HeapError),
+    UnknownHostFunction(u64),
+    StepLimitExceeded,
+    /// The integer arithmetic of the instruction overflowed while in [ArithmeticMode::Checked]
+    ArithmeticOverflow(Instruction),
+    /// [`RET_FRAME`](Instruction::RET_FRAME) was executed with an empty call stack
+    FrameError,
+    /// [`HALT`](Instruction::HALT) was executed
+    Halted,
+    /// A step was attempted without enough fuel remaining for its instruction
+    OutOfFuel,
+    /// More instructions were decoded than allowed by
+    /// [decode_program_with_limit](crate::instruction::decode_program_with_limit)
+    DecodeLimitExceeded,
+    /// A program file did not start with [MAGIC](crate::program_file::MAGIC)
+    InvalidMagic,
+    /// A program file has a version of the format which is not supported
+    UnsupportedVersion(u8),
+    Integrity(IntegrityError)
+}
+
+impl Display for MachineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineError::IO(e) => write!(f, "io error: {}", e),
+            MachineError::UnknownInstruction { opcode, offset } => {
+                write!(f, "unknown instruction opcode {} at offset {:#x}", opcode, offset)
+            }
+            MachineError::InvalidInstruction(InvalidInstruction::InvalidComparisonByte(byte)) => {
+                write!(f, "invalid comparison byte {}, expected one of 0, 1, 2 or 3", byte)
+            }
+            MachineError::EndOfInstructions => write!(f, "reached the end of the instructions"),
+            MachineError::IncompleteInstruction { opcode, offset } => {
+                write!(f, "instruction with opcode {} at offset {:#x} is missing operand bytes", opcode, offset)
+            }
+            MachineError::Stack(e) => write!(f, "stack error: {}", e),
+            MachineError::Heap(e) => write!(f, "heap error: {}", e),
+            MachineError::UnknownHostFunction(id) => write!(f, "no host function is registered with id {}", id),
+            MachineError::StepLimitExceeded => write!(f, "step limit exceeded"),
+            MachineError::ArithmeticOverflow(instruction) => {
+                write!(f, "arithmetic overflow during {}", instruction)
+            }
+            MachineError::FrameError => write!(f, "returned with an empty call stack"),
+            MachineError::Halted => write!(f, "halted"),
+            MachineError::OutOfFuel => write!(f, "out of fuel"),
+            MachineError::DecodeLimitExceeded => write!(f, "instruction decode limit exceeded"),
+            MachineError::InvalidMagic => write!(f, "not a program file, the magic number does not match"),
+            MachineError::UnsupportedVersion(version) => {
+                write!(f, "unsupported program file version {}", version)
+            }
+            MachineError::Integrity(e) => write!(f, "integrity error: {}", e)
+        }
+    }
+}
+
+impl Error for MachineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MachineError::IO(e) => Some(e),
+            MachineError::Stack(e) => Some(e),
+            MachineError::Heap(e) => Some(e),
+            MachineError::Integrity(e) => Some(e),
+            _ => None
+        }
+    }
 }
 
 impl From<io::Error> for MachineError {
@@ -169,6 +340,12 @@ impl From<io::Error> for MachineError {
     }
 }
 
+impl From<IntegrityError> for MachineError {
+    fn from(e: IntegrityError) -> Self {
+        MachineError::Integrity(e)
+    }
+}
+
 impl From<StackError> for MachineError {
     fn from(e: StackError) -> Self {
         MachineError::Stack(e)
@@ -193,23 +370,401 @@ impl From<InvalidInstruction> for MachineError {
     }
 }
 
-pub struct Machine {
-    pub(crate) instructions: Box<dyn ReadSeek>,
-    pub(crate) stack: Box<dyn ComputeStack>,
-    pub(crate) heap: Heap
+/// A function provided by the host, callable from bytecode through [`CALL_EXT`](Instruction::CALL_EXT)
+pub type HostFunction = Box<dyn FnMut(&mut dyn ComputeStack, &mut Heap) -> Result<HostReturn, MachineError>>;
+
+/// A function provided by the host, callable from bytecode through
+/// [`CALL_EXT_N`](Instruction::CALL_EXT_N), which receives its arguments as bytes borrowed from
+/// the top of the stack
+pub type ArgumentHostFunction = Box<dyn FnMut(&[u8], &mut Heap) -> Result<HostReturn, MachineError>>;
+
+/// What a host function returns to the bytecode calling it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostReturn {
+    None,
+    /// Bytes the machine pushes onto the stack once the call returns
+    Bytes(Vec<u8>)
+}
+
+/// The state of a [Machine] captured by [Machine::snapshot]
+pub struct MachineSnapshot {
+    stack: Vec<u8>,
+    heap: Heap,
+    call_stack: Vec<u64>,
+    instruction_pointer: u64
+}
+
+/// Called with each instruction stepped by a [Machine], and the stack as it is before executing it
+pub type TraceHook = Box<dyn FnMut(&Instruction, &dyn ComputeStack)>;
+
+/// The fuel consumed by stepping an instruction, see [Machine::set_fuel]
+pub type GasCost = Box<dyn Fn(&Instruction) -> u64>;
+
+/// The default [GasCost], where heap operations cost more than the rest, which cost 1
+pub fn gas_cost(instruction: &Instruction) -> u64 {
+    use Instruction::*;
+    match instruction {
+        GC => 100,
+        ALLOC | MOV_ST_HP_N | MOV_HP_HP | FILL | CALL_EXT | CALL_EXT_N => 10,
+        COPY_REF | SET_CHILD | GET_CHILD | FREE |
+        MOV_ST_HP_1 | MOV_ST_HP_2 | MOV_ST_HP_4 | MOV_ST_HP_8 |
+        MOV_HP_ST_1 | MOV_HP_ST_2 | MOV_HP_ST_4 | MOV_HP_ST_8 => 3,
+        _ => 1
+    }
+}
+
+/// How the integer `ADD`, `SUB` and `MUL` instructions handle overflow
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ArithmeticMode {
+    /// Wraps around at the boundary of the type
+    Wrapping,
+    /// Fails with [MachineError::ArithmeticOverflow]
+    Checked,
+    /// Clamps to the minimum or maximum value of the type
+    Saturating
+}
+
+/// Why [Machine::run_block] stopped
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BlockEnd {
+    /// The instruction pointer reached the target offset
+    Reached,
+    /// The next instruction is a [branch](Instruction::is_branch), left to be stepped
+    Branch,
+    /// Too little fuel remains for the next instruction, left to be stepped
+    OutOfFuel,
+    /// The end of the instructions was reached, which [Machine::run] also finishes on
+    EndOfInstructions
+}
+
+/// A machine which calls through trait objects, able to swap its stack at runtime
+pub type BoxedMachine = Machine<Box<dyn ReadSeek>, Box<dyn ComputeStack>>;
+
+/// Executes instructions read from `R`, using `S` as its stack
+///
+/// by default both are boxed trait objects, see [BoxedMachine], while naming concrete types, such
+/// as with [Machine::from_parts], lets the hot loop be monomorphized
+pub struct Machine<R = Box<dyn ReadSeek>, S = Box<dyn ComputeStack>> {
+    pub(crate) instructions: R,
+    pub(crate) stack: S,
+    pub(crate) heap: Heap,
+    pub(crate) host_functions: HashMap<u64, HostFunction>,
+    pub(crate) argument_host_functions: HashMap<u64, ArgumentHostFunction>,
+    pub(crate) arithmetic_mode: ArithmeticMode,
+    /// return addresses of [`CALL_FRAME`](Instruction::CALL_FRAME), kept apart from the data on `stack`
+    pub(crate) call_stack: Vec<u64>,
+    pub(crate) trace: Option<TraceHook>,
+    pub(crate) step_count: u64,
+    /// execution counts by opcode, only recorded once profiling is enabled
+    pub(crate) histogram: Option<HashMap<u8, u64>>,
+    /// fuel remaining before failing with [MachineError::OutOfFuel], unlimited if [None]
+    pub(crate) fuel: Option<u64>,
+    pub(crate) gas_cost: GasCost,
+    /// references popped by the instruction being executed, which keep their stack reference
+    /// until it is done with them
+    pub(crate) popped_references: Vec<ObjectReference>
 }
 
 impl Machine {
+    /// Creates a machine executing `instructions`, with an unbounded stack and heap
+    pub fn new(instructions: Box<dyn ReadSeek>) -> Self {
+        Machine::from_parts(instructions, Box::new(Vec::new()))
+    }
+
+    /// Creates a machine executing `program`, see [Machine::new]
+    pub fn from_bytecode(program: Vec<u8>) -> Self {
+        Self::new(Box::new(Cursor::new(program)))
+    }
+
+    /// Creates a machine executing a copy of `program`, see [Machine::new]
+    pub fn from_bytecode_slice(program: &[u8]) -> Self {
+        Self::from_bytecode(program.to_vec())
+    }
+
+    /// Creates a machine executing `program` without copying it, so the same program can be
+    /// executed by many machines, see [Machine::new]
+    pub fn from_shared_bytecode(program: Arc<[u8]>) -> Self {
+        Self::new(Box::new(ByteCode::new(program)))
+    }
+
+    /// Creates a machine executing the program file at `path`, once its header is validated by
+    /// [read_and_validate_header] and its checksum by [verify_checksum]
+    ///
+    /// a file too short to hold a header fails with [IntegrityError::Truncated]\
+    /// addresses in the program are relative to the end of the header
+    pub fn from_program_file<P: AsRef<Path>>(path: P) -> Result<Self, MachineError> {
+        let mut program = std::fs::read(path)?;
+        if program.len() < HEADER_LEN {
+            return Err(IntegrityError::Truncated.into());
+        }
+        read_and_validate_header(&mut &program[..])?;
+        verify_checksum(&program)?;
+        program.drain(..HEADER_LEN);
+        Ok(Self::from_bytecode(program))
+    }
+
+    pub fn with_stack(mut self, stack: Box<dyn ComputeStack>) -> Self {
+        self.stack = stack;
+        self
+    }
+}
+
+impl<R: ReadSeek, S: ComputeStack> Machine<R, S> {
+    /// Creates a machine executing `instructions` with `stack`, and an unbounded heap
+    pub fn from_parts(instructions: R, stack: S) -> Self {
+        Self {
+            instructions,
+            stack,
+            heap: Heap::new(),
+            host_functions: HashMap::new(),
+            argument_host_functions: HashMap::new(),
+            arithmetic_mode: ArithmeticMode::Wrapping,
+            call_stack: Vec::new(),
+            trace: None,
+            step_count: 0,
+            histogram: None,
+            fuel: None,
+            gas_cost: Box::new(gas_cost),
+            popped_references: Vec::new()
+        }
+    }
+
+    /// Reserves room for `additional` more bytes on the stack, for programs known to push a lot,
+    /// see [ComputeStack::reserve]
+    pub fn reserve_stack(&mut self, additional: usize) -> &mut Self {
+        self.stack.reserve(additional);
+        self
+    }
+
+    pub fn with_heap(mut self, heap: Heap) -> Self {
+        self.heap = heap;
+        self
+    }
+
+    pub fn with_arithmetic_mode(mut self, arithmetic_mode: ArithmeticMode) -> Self {
+        self.arithmetic_mode = arithmetic_mode;
+        self
+    }
+
+    pub fn stack(&self) -> &S {
+        &self.stack
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn register_host_fn<F>(&mut self, id: u64, f: F) -> &mut Self
+    where F: FnMut(&mut dyn ComputeStack, &mut Heap) -> Result<HostReturn, MachineError> + 'static {
+        self.host_functions.insert(id, Box::new(f));
+        self
+    }
+
+    /// Registers a function for [`CALL_EXT_N`](Instruction::CALL_EXT_N), whose ids are separate
+    /// from those of [Machine::register_host_fn]
+    pub fn register_host_fn_n<F>(&mut self, id: u64, f: F) -> &mut Self
+    where F: FnMut(&[u8], &mut Heap) -> Result<HostReturn, MachineError> + 'static {
+        self.argument_host_functions.insert(id, Box::new(f));
+        self
+    }
+
     pub fn step(&mut self) -> Result<(), (MachineError, Option<Instruction>)> {
+        self.step_debug()?;
+        Ok(())
+    }
+
+    /// Same as [Machine::step], but returns the executed instruction
+    pub fn step_debug(&mut self) -> Result<Instruction, (MachineError, Option<Instruction>)> {
+        let address = match self.fuel {
+            Some(_) => Some(self.instructions.stream_position().map_err(|e| (e.into(), None))?),
+            None => None
+        };
         let instruction = Instruction::from_reader(&mut self.instructions)
             .map_err(|e| (e, None))?;
-        println!("executing {:?}", instruction);
-        self.execute(instruction)
-            .map_err(|e| (e, Some(instruction)))?;
+        if let (Some(fuel), Some(address)) = (&mut self.fuel, address) {
+            match fuel.checked_sub((self.gas_cost)(&instruction)) {
+                Some(remaining) => *fuel = remaining,
+                None => {
+                    // rewinding, so that the instruction is stepped again once refueled
+                    self.instructions.seek(SeekFrom::Start(address)).map_err(|e| (e.into(), Some(instruction)))?;
+                    return Err((MachineError::OutOfFuel, Some(instruction)));
+                }
+            }
+        }
+        if let Some(trace) = &mut self.trace {
+            trace(&instruction, &self.stack);
+        }
+        self.step_count += 1;
+        if let Some(histogram) = &mut self.histogram {
+            *histogram.entry(instruction.opcode()).or_insert(0) += 1;
+        }
+        match self.execute(instruction) {
+            Ok(()) => Ok(instruction),
+            Err(MachineError::Halted) => {
+                // staying on the HALT, so that stepping again halts again rather than executing
+                // whatever follows it
+                let length = instruction.encoded_len() as i64;
+                self.instructions.seek(SeekFrom::Current(-length)).map_err(|e| (e.into(), Some(instruction)))?;
+                Err((MachineError::Halted, Some(instruction)))
+            }
+            Err(e) => Err((e, Some(instruction)))
+        }
+    }
+
+    pub fn set_trace<F>(&mut self, f: F) -> &mut Self
+    where F: FnMut(&Instruction, &dyn ComputeStack) + 'static {
+        self.trace = Some(Box::new(f));
+        self
+    }
+
+    /// The address of the next instruction to be stepped
+    pub fn instruction_pointer(&mut self) -> io::Result<u64> {
+        self.instructions.stream_position()
+    }
+
+    /// Sets the address of the next instruction to be stepped
+    pub fn jump_to(&mut self, address: u64) -> io::Result<()> {
+        self.instructions.seek(SeekFrom::Start(address))?;
+        Ok(())
+    }
+
+    /// Captures the stack, heap, call stack and instruction pointer, to later be put back with
+    /// [Machine::restore]
+    pub fn snapshot(&mut self) -> Result<MachineSnapshot, MachineError> {
+        Ok(MachineSnapshot {
+            stack: self.stack.read_at(0, self.stack.size())?.to_vec(),
+            heap: self.heap.clone(),
+            call_stack: self.call_stack.clone(),
+            instruction_pointer: self.instructions.stream_position()?
+        })
+    }
+
+    pub fn restore(&mut self, snapshot: MachineSnapshot) -> Result<(), MachineError> {
+        self.stack.clear();
+        self.stack.reserve(snapshot.stack.len());
+        self.stack.push_slice(&snapshot.stack)?;
+        self.heap = snapshot.heap;
+        self.call_stack = snapshot.call_stack;
+        self.instructions.seek(SeekFrom::Start(snapshot.instruction_pointer))?;
         Ok(())
     }
 
+    /// Limits execution to `fuel`, where each step consumes fuel according to the gas cost of its
+    /// instruction, and stepping an instruction costing more than the remaining fuel fails with
+    /// [OutOfFuel](MachineError::OutOfFuel)
+    pub fn set_fuel(&mut self, fuel: u64) -> &mut Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Replaces the default [gas_cost] used to consume fuel
+    pub fn set_gas_cost<F>(&mut self, f: F) -> &mut Self
+    where F: Fn(&Instruction) -> u64 + 'static {
+        self.gas_cost = Box::new(f);
+        self
+    }
+
+    /// The fuel remaining, or [None] if execution is unlimited
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// The number of instructions stepped so far
+    pub fn steps_executed(&self) -> u64 {
+        self.step_count
+    }
+
+    /// Starts recording how many times each opcode is stepped, see [Machine::histogram]
+    pub fn enable_profiling(&mut self) -> &mut Self {
+        self.histogram.get_or_insert_with(HashMap::new);
+        self
+    }
+
+    /// Execution counts by opcode, if profiling has been enabled
+    pub fn histogram(&self) -> Option<&HashMap<u8, u64>> {
+        self.histogram.as_ref()
+    }
+
+    /// Steps until the end of the instructions is reached\
+    /// a [`HALT`](Instruction::HALT) stops execution early, with [`Halted`](MachineError::Halted)
+    pub fn run(&mut self) -> Result<(), (MachineError, Option<Instruction>)> {
+        loop {
+            match self.step() {
+                Ok(_) => {}
+                Err((MachineError::EndOfInstructions, _)) => return Ok(()),
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    /// Steps until the end of the instructions is reached, or fails with
+    /// [`StepLimitExceeded`](MachineError::StepLimitExceeded) if `max_steps` steps have been
+    /// executed without reaching it
+    pub fn run_with_limit(&mut self, max_steps: u64) -> Result<(), (MachineError, Option<Instruction>)> {
+        for _ in 0..max_steps {
+            match self.step() {
+                Ok(_) => {}
+                Err((MachineError::EndOfInstructions, _)) => return Ok(()),
+                Err(e) => return Err(e)
+            }
+        }
+        Err((MachineError::StepLimitExceeded, None))
+    }
+
+    /// Executes the straight-line instructions from the instruction pointer up to `until`, without
+    /// calling the trace hook or recording the profiling histogram, which makes hot loops cheaper
+    /// than repeatedly calling [Machine::step]
+    ///
+    /// the block stops before the first [branch](Instruction::is_branch), or instruction there
+    /// isn't enough fuel for, leaving the instruction pointer on it so that it can be stepped, and
+    /// returns why it stopped
+    pub fn run_block(&mut self, until: u64) -> Result<BlockEnd, (MachineError, Option<Instruction>)> {
+        loop {
+            let address = self.instructions.stream_position().map_err(|e| (e.into(), None))?;
+            if address >= until {
+                return Ok(BlockEnd::Reached);
+            }
+            let instruction = match Instruction::from_reader(&mut self.instructions) {
+                Ok(instruction) => instruction,
+                Err(MachineError::EndOfInstructions) => return Ok(BlockEnd::EndOfInstructions),
+                Err(e) => return Err((e, None))
+            };
+            let cost = (self.gas_cost)(&instruction);
+            let end = if instruction.is_branch() {
+                Some(BlockEnd::Branch)
+            } else if self.fuel.is_some_and(|fuel| fuel < cost) {
+                Some(BlockEnd::OutOfFuel)
+            } else {
+                None
+            };
+            if let Some(end) = end {
+                self.instructions.seek(SeekFrom::Start(address)).map_err(|e| (e.into(), Some(instruction)))?;
+                return Ok(end);
+            }
+            if let Some(fuel) = &mut self.fuel {
+                *fuel -= cost;
+            }
+            self.step_count += 1;
+            self.execute(instruction)
+                .map_err(|e| (e, Some(instruction)))?;
+        }
+    }
+
     pub fn execute(&mut self, instruction: Instruction) -> Result<(), MachineError> {
+        let result = self.execute_instruction(instruction);
+        // only released now, so that a heap which auto collects can't remove an object while the
+        // instruction that popped its last reference is still using it
+        let mut released = Ok(());
+        for obj_ref in std::mem::take(&mut self.popped_references) {
+            let decremented = self.heap.decrement_stack_references(obj_ref);
+            if released.is_ok() {
+                released = decremented;
+            }
+        }
+        result.and(released.map_err(Into::into))
+    }
+
+    fn execute_instruction(&mut self, instruction: Instruction) -> Result<(), MachineError> {
         use Instruction::*;
         match instruction {
             PSH_1(value) => self.stack.push_slice(&value)?,
@@ -221,18 +776,21 @@ impl Machine {
             POP_4 => self.stack.remove_top(4)?,
             POP_8 => self.stack.remove_top(8)?,
             ALLOC => {
-                let children_length = self.stack.pop_u64()?;
-                let data_length = self.stack.pop_u64()?;
-                let obj_ref = self.heap.allocate(children_length as usize, data_length as usize)?;
+                // lengths which do not fit in a usize could never be allocated anyway
+                let children_length = usize::try_from(self.stack.pop_u64()?).map_err(|_| HeapError::Allocation)?;
+                let data_length = usize::try_from(self.stack.pop_u64()?).map_err(|_| HeapError::Allocation)?;
+                let obj_ref = self.heap.allocate(children_length, data_length)?;
                 self.stack.push_u64(obj_ref.into())?;
             }
             COPY_REF => {
-                // intentionally not using Machine::stack_pop_object_reference so that I can avoid
-                // having to increment the stack references twice to make up for the one popped
-                let obj_ref = ObjectReference::new_result(self.stack.pop_u64()?)?;
+                // peeking rather than popping, so that the stack is left untouched if the reference
+                // count can't be incremented
+                let obj_ref = ObjectReference::new_result(self.stack.peek_u64()?)?;
                 self.heap.increment_stack_references(&obj_ref)?;
-                self.stack.push_u64(obj_ref.clone().into())?;
-                self.stack.push_u64(obj_ref.into())?;
+                if let Err(e) = self.stack.push_u64(obj_ref.clone().into()) {
+                    self.heap.decrement_stack_references(obj_ref)?;
+                    return Err(e.into());
+                }
             }
             SET_CHILD => {
                 let parent = self.stack_pop_object_reference()?;
@@ -244,7 +802,7 @@ impl Machine {
                 let parent = self.stack_pop_object_reference()?;
                 let child_index = self.stack.pop_u64()? as usize;
                 match self.heap.get_child(&parent, child_index)? {
-                    None => self.stack.push_u64(0)?,
+                    None => self.stack.push_u64(NULL_REFERENCE)?,
                     Some(child) => {
                         self.heap.increment_stack_references(&child)?;
                         self.stack.push_u64(child.into())?
@@ -277,7 +835,7 @@ impl Machine {
             }
             JSR => {
                 let address = self.stack.pop_u64()?;
-                let next_address = self.instructions.stream_position()? + 1;
+                let next_address = self.instructions.stream_position()?;
                 self.stack.push_u64(next_address)?;
                 self.instructions.seek(SeekFrom::Start(address))?;
             }
@@ -328,10 +886,10 @@ impl Machine {
                 compare_instruction_impl!(self, i64);
             }
             CMP_F4 => {
-                compare_instruction_impl!(self, f32);
+                compare_instruction_impl!(self, f32, .total_cmp);
             }
             CMP_F8 => {
-                compare_instruction_impl!(self, f64);
+                compare_instruction_impl!(self, f64, .total_cmp);
             }
             NOT_1 => {
                 not_instruction_impl!(self, u8);
@@ -412,46 +970,46 @@ impl Machine {
                 shift_instruction_impl!(self, i16, >>);
             }
             SAR_4 => {
-                shift_instruction_impl!(self, u32, >>);
+                shift_instruction_impl!(self, i32, >>);
             }
             SAR_8 => {
-                shift_instruction_impl!(self, u64, >>);
+                shift_instruction_impl!(self, i64, >>);
             }
             ADD_1 => {
-                two_argument_instruction_impl!(self, u8, +);
+                integer_arithmetic_instruction_impl!(self, instruction, u8, +);
             }
             ADD_2 => {
-                two_argument_instruction_impl!(self, u16, +);
+                integer_arithmetic_instruction_impl!(self, instruction, u16, +);
             }
             ADD_4 => {
-                two_argument_instruction_impl!(self, u32, +);
+                integer_arithmetic_instruction_impl!(self, instruction, u32, +);
             }
             ADD_8 => {
-                two_argument_instruction_impl!(self, u64, +);
+                integer_arithmetic_instruction_impl!(self, instruction, u64, +);
             }
             SUB_1 => {
-                two_argument_instruction_impl!(self, u8, -);
+                integer_arithmetic_instruction_impl!(self, instruction, u8, -);
             }
             SUB_2 => {
-                two_argument_instruction_impl!(self, u16, -);
+                integer_arithmetic_instruction_impl!(self, instruction, u16, -);
             }
             SUB_4 => {
-                two_argument_instruction_impl!(self, u32, -);
+                integer_arithmetic_instruction_impl!(self, instruction, u32, -);
             }
             SUB_8 => {
-                two_argument_instruction_impl!(self, u64, -);
+                integer_arithmetic_instruction_impl!(self, instruction, u64, -);
             }
             MUL_1 => {
-                two_argument_instruction_impl!(self, u8, *);
+                integer_arithmetic_instruction_impl!(self, instruction, u8, *);
             }
             MUL_2 => {
-                two_argument_instruction_impl!(self, u16, *);
+                integer_arithmetic_instruction_impl!(self, instruction, u16, *);
             }
             MUL_4 => {
-                two_argument_instruction_impl!(self, u32, *);
+                integer_arithmetic_instruction_impl!(self, instruction, u32, *);
             }
             MUL_8 => {
-                two_argument_instruction_impl!(self, u64, *);
+                integer_arithmetic_instruction_impl!(self, instruction, u64, *);
             }
             DIV_REM_U_1 => {
                 div_rem_instruction_impl!(self, u8);
@@ -502,7 +1060,7 @@ impl Machine {
                 two_argument_instruction_impl!(self, f64, /);
             }
             REM_F_4 => {
-                two_argument_instruction_impl!(self, f64, %);
+                two_argument_instruction_impl!(self, f32, %);
             }
             REM_F_8 => {
                 two_argument_instruction_impl!(self, f64, %);
@@ -538,7 +1096,325 @@ impl Machine {
                 convert_instruction_impl!(self, f64 -> f32);
             }
             CALL_EXT => {
-                todo!()
+                let id = self.stack.pop_u64()?;
+                let f = self.host_functions.get_mut(&id).ok_or(MachineError::UnknownHostFunction(id))?;
+                let result = f(&mut self.stack, &mut self.heap)?;
+                self.push_host_return(result)?;
+            }
+            DUP_1 => {
+                dup_instruction_impl!(self, 1);
+            }
+            DUP_2 => {
+                dup_instruction_impl!(self, 2);
+            }
+            DUP_4 => {
+                dup_instruction_impl!(self, 4);
+            }
+            DUP_8 => {
+                dup_instruction_impl!(self, 8);
+            }
+            SWAP_1 => {
+                swap_instruction_impl!(self, 1);
+            }
+            SWAP_2 => {
+                swap_instruction_impl!(self, 2);
+            }
+            SWAP_4 => {
+                swap_instruction_impl!(self, 4);
+            }
+            SWAP_8 => {
+                swap_instruction_impl!(self, 8);
+            }
+            FREE => {
+                let obj_ref = ObjectReference::new_result(self.stack.pop_u64()?)?;
+                self.heap.free(obj_ref)?;
+            }
+            GC => {
+                let stats = self.heap.collect_garbage();
+                self.stack.push_u64(stats.reclaimed as u64)?;
+            }
+            NEG_F_4 => {
+                one_argument_float_instruction_impl!(self, f32, neg);
+            }
+            NEG_F_8 => {
+                one_argument_float_instruction_impl!(self, f64, neg);
+            }
+            ABS_F_4 => {
+                one_argument_float_instruction_impl!(self, f32, abs);
+            }
+            ABS_F_8 => {
+                one_argument_float_instruction_impl!(self, f64, abs);
+            }
+            MIN_U_1 => {
+                two_argument_instruction_impl!(self, u8, .min);
+            }
+            MIN_U_2 => {
+                two_argument_instruction_impl!(self, u16, .min);
+            }
+            MIN_U_4 => {
+                two_argument_instruction_impl!(self, u32, .min);
+            }
+            MIN_U_8 => {
+                two_argument_instruction_impl!(self, u64, .min);
+            }
+            MAX_U_1 => {
+                two_argument_instruction_impl!(self, u8, .max);
+            }
+            MAX_U_2 => {
+                two_argument_instruction_impl!(self, u16, .max);
+            }
+            MAX_U_4 => {
+                two_argument_instruction_impl!(self, u32, .max);
+            }
+            MAX_U_8 => {
+                two_argument_instruction_impl!(self, u64, .max);
+            }
+            MIN_S_1 => {
+                two_argument_instruction_impl!(self, i8, .min);
+            }
+            MIN_S_2 => {
+                two_argument_instruction_impl!(self, i16, .min);
+            }
+            MIN_S_4 => {
+                two_argument_instruction_impl!(self, i32, .min);
+            }
+            MIN_S_8 => {
+                two_argument_instruction_impl!(self, i64, .min);
+            }
+            MAX_S_1 => {
+                two_argument_instruction_impl!(self, i8, .max);
+            }
+            MAX_S_2 => {
+                two_argument_instruction_impl!(self, i16, .max);
+            }
+            MAX_S_4 => {
+                two_argument_instruction_impl!(self, i32, .max);
+            }
+            MAX_S_8 => {
+                two_argument_instruction_impl!(self, i64, .max);
+            }
+            ROL_1 => {
+                rotate_instruction_impl!(self, u8, rotate_left);
+            }
+            ROL_2 => {
+                rotate_instruction_impl!(self, u16, rotate_left);
+            }
+            ROL_4 => {
+                rotate_instruction_impl!(self, u32, rotate_left);
+            }
+            ROL_8 => {
+                rotate_instruction_impl!(self, u64, rotate_left);
+            }
+            ROR_1 => {
+                rotate_instruction_impl!(self, u8, rotate_right);
+            }
+            ROR_2 => {
+                rotate_instruction_impl!(self, u16, rotate_right);
+            }
+            ROR_4 => {
+                rotate_instruction_impl!(self, u32, rotate_right);
+            }
+            ROR_8 => {
+                rotate_instruction_impl!(self, u64, rotate_right);
+            }
+            CLZ_1 => {
+                count_instruction_impl!(self, u8, leading_zeros);
+            }
+            CLZ_2 => {
+                count_instruction_impl!(self, u16, leading_zeros);
+            }
+            CLZ_4 => {
+                count_instruction_impl!(self, u32, leading_zeros);
+            }
+            CLZ_8 => {
+                count_instruction_impl!(self, u64, leading_zeros);
+            }
+            CTZ_1 => {
+                count_instruction_impl!(self, u8, trailing_zeros);
+            }
+            CTZ_2 => {
+                count_instruction_impl!(self, u16, trailing_zeros);
+            }
+            CTZ_4 => {
+                count_instruction_impl!(self, u32, trailing_zeros);
+            }
+            CTZ_8 => {
+                count_instruction_impl!(self, u64, trailing_zeros);
+            }
+            POPCNT_1 => {
+                count_instruction_impl!(self, u8, count_ones);
+            }
+            POPCNT_2 => {
+                count_instruction_impl!(self, u16, count_ones);
+            }
+            POPCNT_4 => {
+                count_instruction_impl!(self, u32, count_ones);
+            }
+            POPCNT_8 => {
+                count_instruction_impl!(self, u64, count_ones);
+            }
+            LOAD_8 => {
+                let offset = self.stack.pop_u64()? as usize;
+                let value = self.stack.read_at(offset, 8)?.to_vec();
+                self.stack.push_slice(&value)?;
+            }
+            STORE_8 => {
+                let offset = self.stack.pop_u64()? as usize;
+                let mut value = [0; 8];
+                self.stack.pop_into(&mut value)?;
+                self.stack.write_at(offset, &value)?;
+            }
+            CALL_FRAME => {
+                let address = self.stack.pop_u64()?;
+                let next_address = self.instructions.stream_position()?;
+                self.call_stack.push(next_address);
+                self.instructions.seek(SeekFrom::Start(address))?;
+            }
+            RET_FRAME => {
+                let address = self.call_stack.pop().ok_or(MachineError::FrameError)?;
+                self.instructions.seek(SeekFrom::Start(address))?;
+            }
+            HALT => {
+                return Err(MachineError::Halted);
+            }
+            CMP_REF => {
+                let a = self.stack_pop_nullable_object_reference()?;
+                let b = self.stack_pop_nullable_object_reference()?;
+                let cmp: PossiblyOrdering = match (a, b) {
+                    (Some(a), Some(b)) => u64::from(a).cmp(&u64::from(b)).into(),
+                    _ => PossiblyOrdering::Unordered
+                };
+                self.stack.push_u8(cmp as u8)?;
+            }
+            IS_NULL => {
+                let obj_ref = self.stack_pop_nullable_object_reference()?;
+                self.stack.push_u8(obj_ref.is_none() as u8)?;
+            }
+            OBJ_CHILDREN_LEN => {
+                let obj_ref = self.stack_pop_object_reference()?;
+                let children_len = self.heap.children_len(&obj_ref)?;
+                self.stack.push_u64(children_len as u64)?;
+            }
+            OBJ_DATA_LEN => {
+                let obj_ref = self.stack_pop_object_reference()?;
+                let data_len = self.heap.data_len(&obj_ref)?;
+                self.stack.push_u64(data_len as u64)?;
+            }
+            MOV_ST_HP_N => {
+                let obj_ref = self.stack_pop_object_reference()?;
+                let start = self.stack.pop_u64()? as usize;
+                let length = self.stack.pop_u64()? as usize;
+                // the range is validated before the data is removed, so data which does not fit
+                // stays on the stack
+                self.heap.get_mut_data_slice(&obj_ref, start, length)?.copy_from_slice(self.stack.peek_slice(length)?);
+                self.stack.remove_top(length)?;
+            }
+            MOV_HP_HP => {
+                let destination = self.stack_pop_object_reference()?;
+                let destination_start = self.stack.pop_u64()? as usize;
+                let source = self.stack_pop_object_reference()?;
+                let source_start = self.stack.pop_u64()? as usize;
+                let length = self.stack.pop_u64()? as usize;
+                self.heap.copy_data(&source, source_start, &destination, destination_start, length)?;
+            }
+            FILL => {
+                let obj_ref = self.stack_pop_object_reference()?;
+                let start = self.stack.pop_u64()? as usize;
+                let length = self.stack.pop_u64()? as usize;
+                let value = self.stack.pop_u8()?;
+                self.heap.get_mut_data_slice(&obj_ref, start, length)?.fill(value);
+            }
+            CNV_U1_U8 => {
+                convert_instruction_impl!(self, u8 -> u64);
+            }
+            CNV_U2_U8 => {
+                convert_instruction_impl!(self, u16 -> u64);
+            }
+            CNV_U4_U8 => {
+                convert_instruction_impl!(self, u32 -> u64);
+            }
+            CNV_S1_S8 => {
+                convert_instruction_impl!(self, i8 -> i64);
+            }
+            CNV_S2_S8 => {
+                convert_instruction_impl!(self, i16 -> i64);
+            }
+            CNV_S4_S8 => {
+                convert_instruction_impl!(self, i32 -> i64);
+            }
+            CNV_U8_U1 => {
+                convert_instruction_impl!(self, u64 -> u8);
+            }
+            CNV_U8_U2 => {
+                convert_instruction_impl!(self, u64 -> u16);
+            }
+            CNV_U8_U4 => {
+                convert_instruction_impl!(self, u64 -> u32);
+            }
+            SQRT_F_4 => {
+                one_argument_float_instruction_impl!(self, f32, sqrt);
+            }
+            SQRT_F_8 => {
+                one_argument_float_instruction_impl!(self, f64, sqrt);
+            }
+            SIN_F_4 => {
+                one_argument_float_instruction_impl!(self, f32, sin);
+            }
+            SIN_F_8 => {
+                one_argument_float_instruction_impl!(self, f64, sin);
+            }
+            COS_F_4 => {
+                one_argument_float_instruction_impl!(self, f32, cos);
+            }
+            COS_F_8 => {
+                one_argument_float_instruction_impl!(self, f64, cos);
+            }
+            FMA_F_4 => {
+                three_argument_instruction_impl!(self, f32, .mul_add);
+            }
+            FMA_F_8 => {
+                three_argument_instruction_impl!(self, f64, .mul_add);
+            }
+            IS_NAN_4 => {
+                predicate_instruction_impl!(self, f32, is_nan);
+            }
+            IS_NAN_8 => {
+                predicate_instruction_impl!(self, f64, is_nan);
+            }
+            IS_INF_4 => {
+                predicate_instruction_impl!(self, f32, is_infinite);
+            }
+            IS_INF_8 => {
+                predicate_instruction_impl!(self, f64, is_infinite);
+            }
+            JMP_IMM(address) => {
+                self.instructions.seek(SeekFrom::Start(u64::from_le_bytes(address)))?;
+            }
+            JMP_IF => {
+                let condition = self.stack.pop_u8()?;
+                let address = self.stack.pop_u64()?;
+                if condition != 0 {
+                    self.instructions.seek(SeekFrom::Start(address))?;
+                }
+            }
+            JMP_IF_NOT => {
+                let condition = self.stack.pop_u8()?;
+                let address = self.stack.pop_u64()?;
+                if condition == 0 {
+                    self.instructions.seek(SeekFrom::Start(address))?;
+                }
+            }
+            CALL_EXT_N => {
+                let id = self.stack.pop_u64()?;
+                // a length beyond usize::MAX can never fit on the stack, and fails as an underflow
+                let length = usize::try_from(self.stack.pop_u64()?).unwrap_or(usize::MAX);
+                let f = self.argument_host_functions.get_mut(&id).ok_or(MachineError::UnknownHostFunction(id))?;
+                let result = f(self.stack.peek_slice(length)?, &mut self.heap)?;
+                self.stack.remove_top(length)?;
+                self.push_host_return(result)?;
+            }
+            PSH_NULL => {
+                self.stack.push_u64(NULL_REFERENCE)?;
             }
         }
         Ok(())
@@ -547,16 +1423,1492 @@ impl Machine {
     fn stack_pop_nullable_object_reference(&mut self) -> Result<Option<ObjectReference>, MachineError> {
         match ObjectReference::new_option(self.stack.pop_u64()?) {
             None => Ok(None),
-            Some(obj_ref) => {
-                self.heap.decrement_stack_references(obj_ref.clone())?;
-                Ok(Some(obj_ref))
-            }
+            Some(obj_ref) => self.release_after_execution(obj_ref).map(Some)
         }
     }
 
+    fn push_host_return(&mut self, result: HostReturn) -> Result<(), MachineError> {
+        if let HostReturn::Bytes(bytes) = result {
+            self.stack.push_slice(&bytes)?;
+        }
+        Ok(())
+    }
+
     fn stack_pop_object_reference(&mut self) -> Result<ObjectReference, MachineError> {
         let obj_ref = ObjectReference::new_result(self.stack.pop_u64()?)?;
-        self.heap.decrement_stack_references(obj_ref.clone())?;
+        self.release_after_execution(obj_ref)
+    }
+
+    /// Checks that a popped reference still refers to an object, deferring only the removal of its
+    /// stack reference until the instruction is done, see [Machine::execute]
+    fn release_after_execution(&mut self, obj_ref: ObjectReference) -> Result<ObjectReference, MachineError> {
+        if !self.heap.contains(&obj_ref) {
+            return Err(HeapError::ObjectNotFound.into());
+        }
+        self.popped_references.push(obj_ref.clone());
         Ok(obj_ref)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::compute_stack::BoundedStack;
+    use crate::instruction::Instruction::*;
+    use crate::compute_stack::ArrayStack;
+    use crate::InstructionReceiver;
+
+    fn machine() -> Machine {
+        Machine::new(Box::new(Cursor::new(Vec::new())))
+    }
+
+    #[test]
+    fn sar_keeps_the_sign() {
+        let mut m = machine();
+        m.stack.push_u8(1).unwrap();
+        m.stack.push_i8(-8).unwrap();
+        m.execute(SAR_1).unwrap();
+        assert_eq!(m.stack.pop_i8().unwrap(), -4);
+        m.stack.push_u8(1).unwrap();
+        m.stack.push_i16(-8).unwrap();
+        m.execute(SAR_2).unwrap();
+        assert_eq!(m.stack.pop_i16().unwrap(), -4);
+        m.stack.push_u8(1).unwrap();
+        m.stack.push_i32(-8).unwrap();
+        m.execute(SAR_4).unwrap();
+        assert_eq!(m.stack.pop_i32().unwrap(), -4);
+        m.stack.push_u8(1).unwrap();
+        m.stack.push_i64(-8).unwrap();
+        m.execute(SAR_8).unwrap();
+        assert_eq!(m.stack.pop_i64().unwrap(), -4);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn rem_f_4_uses_two_f32() {
+        let mut m = machine();
+        m.stack.push_u64(u64::MAX).unwrap();
+        m.stack.push_f32(3.0).unwrap();
+        m.stack.push_f32(7.5).unwrap();
+        m.execute(REM_F_4).unwrap();
+        assert_eq!(m.stack.size(), 12);
+        assert_eq!(m.stack.pop_f32().unwrap(), 1.5);
+        assert_eq!(m.stack.pop_u64().unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn ret_resumes_after_jsr() {
+        let mut program = Vec::new();
+        program
+            .instruct(PSH_8(12u64.to_le_bytes()))
+            .instruct(JSR)
+            .instruct(PSH_1([7])) // address 10, the return address
+            .instruct(RET); // address 12, the subroutine
+        let mut m = Machine::new(Box::new(Cursor::new(program)));
+        m.step().unwrap();
+        m.step().unwrap();
+        assert_eq!(m.instructions.stream_position().unwrap(), 12);
+        m.step().unwrap();
+        assert_eq!(m.instructions.stream_position().unwrap(), 10);
+        m.step().unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), 7);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn dup_copies_the_top_value() {
+        let mut m = machine();
+        for (instruction, width) in [(DUP_1, 1), (DUP_2, 2), (DUP_4, 4), (DUP_8, 8)] {
+            m.stack.push_u8(0xEE).unwrap();
+            let value: Vec<u8> = (1..=width).collect();
+            m.stack.push_slice(&value).unwrap();
+            m.execute(instruction).unwrap();
+            assert_eq!(m.stack.size(), 1 + 2 * width as usize);
+            assert_eq!(&*m.stack.pop_slice(width as usize).unwrap(), &value[..]);
+            assert_eq!(&*m.stack.pop_slice(width as usize).unwrap(), &value[..]);
+            assert_eq!(m.stack.pop_u8().unwrap(), 0xEE);
+        }
+        assert!(matches!(m.execute(DUP_1), Err(MachineError::Stack(StackError::Underflow))));
+    }
+
+    #[test]
+    fn swap_exchanges_the_top_two_values() {
+        let mut m = machine();
+        m.stack.push_u8(0xEE).unwrap();
+        m.stack.push_u64(1).unwrap();
+        m.stack.push_u64(2).unwrap();
+        m.execute(SWAP_8).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 1);
+        assert_eq!(m.stack.pop_u64().unwrap(), 2);
+        m.stack.push_u8(3).unwrap();
+        m.execute(SWAP_1).unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), 0xEE);
+        assert_eq!(m.stack.pop_u8().unwrap(), 3);
+        m.stack.push_u16(4).unwrap();
+        m.stack.push_u16(5).unwrap();
+        m.execute(SWAP_2).unwrap();
+        assert_eq!(m.stack.pop_u16().unwrap(), 4);
+        assert_eq!(m.stack.pop_u16().unwrap(), 5);
+        m.stack.push_f32(6.0).unwrap();
+        m.stack.push_f32(7.0).unwrap();
+        m.execute(SWAP_4).unwrap();
+        assert_eq!(m.stack.pop_f32().unwrap(), 6.0);
+        assert_eq!(m.stack.pop_f32().unwrap(), 7.0);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn swap_underflow_leaves_the_stack_unchanged() {
+        let mut m = machine();
+        m.stack.push_u32(1).unwrap();
+        m.stack.push_u64(2).unwrap();
+        assert!(matches!(m.execute(SWAP_8), Err(MachineError::Stack(StackError::Underflow))));
+        assert_eq!(m.stack.size(), 12);
+        assert_eq!(m.stack.pop_u64().unwrap(), 2);
+        assert_eq!(m.stack.pop_u32().unwrap(), 1);
+        assert!(matches!(machine().execute(SWAP_1), Err(MachineError::Stack(StackError::Underflow))));
+    }
+
+    #[test]
+    fn call_ext_invokes_the_registered_host_function() {
+        let mut m = machine();
+        m.register_host_fn(7, |stack, _| {
+            let a = stack.pop_u32()?;
+            let b = stack.pop_u32()?;
+            stack.push_u32(a + b)?;
+            Ok(HostReturn::None)
+        });
+        m.stack.push_u32(40).unwrap();
+        m.stack.push_u32(2).unwrap();
+        m.stack.push_u64(7).unwrap();
+        m.execute(CALL_EXT).unwrap();
+        assert_eq!(m.stack.pop_u32().unwrap(), 42);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn call_ext_with_an_unregistered_id_fails() {
+        let mut m = machine();
+        m.stack.push_u64(3).unwrap();
+        assert!(matches!(m.execute(CALL_EXT), Err(MachineError::UnknownHostFunction(3))));
+    }
+
+    fn machine_with(program: impl FnOnce(&mut Vec<u8>)) -> Machine {
+        let mut instructions = Vec::new();
+        program(&mut instructions);
+        Machine::new(Box::new(Cursor::new(instructions)))
+    }
+
+    #[test]
+    fn run_stops_cleanly_at_the_end_of_the_instructions() {
+        let mut m = machine_with(|i| {
+            i.instruct(PSH_4(3u32.to_le_bytes())).instruct(PSH_4(4u32.to_le_bytes())).instruct(ADD_4);
+        });
+        m.run().unwrap();
+        assert_eq!(m.stack.pop_u32().unwrap(), 7);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn run_reports_the_failing_instruction() {
+        let mut m = machine_with(|i| {
+            i.instruct(PSH_1([1])).instruct(SWAP_8).instruct(PSH_1([2]));
+        });
+        assert!(matches!(m.run(), Err((MachineError::Stack(StackError::Underflow), Some(SWAP_8)))));
+        assert_eq!(m.stack.size(), 1);
+    }
+
+    #[test]
+    fn run_with_limit_stops_an_endless_loop() {
+        // jumps back to its own start forever
+        let mut m = machine_with(|i| {
+            i.instruct(PSH_8(0u64.to_le_bytes())).instruct(RET);
+        });
+        assert!(matches!(m.run_with_limit(101), Err((MachineError::StepLimitExceeded, None))));
+        assert_eq!(m.stack.size(), 8);
+    }
+
+    #[test]
+    fn run_with_limit_succeeds_within_the_budget() {
+        let mut m = machine_with(|i| {
+            i.instruct(PSH_1([1])).instruct(PSH_1([2]));
+        });
+        // the final step is the one that finds the end of the instructions
+        assert!(matches!(m.run_with_limit(2), Err((MachineError::StepLimitExceeded, None))));
+        let mut m = machine_with(|i| {
+            i.instruct(PSH_1([1])).instruct(PSH_1([2]));
+        });
+        m.run_with_limit(3).unwrap();
+        assert_eq!(m.stack.size(), 2);
+    }
+
+    #[test]
+    fn free_deallocates_and_double_free_fails() {
+        let mut m = machine();
+        m.stack.push_u64(8).unwrap();
+        m.stack.push_u64(0).unwrap();
+        m.execute(ALLOC).unwrap();
+        let obj_ref = m.stack.pop_u64().unwrap();
+        m.stack.push_u64(obj_ref).unwrap();
+        m.execute(FREE).unwrap();
+        let obj_ref = ObjectReference::new_result(obj_ref).unwrap();
+        assert!(matches!(m.heap.get_data_slice(&obj_ref, 0, 8), Err(HeapError::ObjectNotFound)));
+        m.stack.push_u64(obj_ref.into()).unwrap();
+        assert!(matches!(m.execute(FREE), Err(MachineError::Heap(HeapError::ObjectNotFound))));
+    }
+
+    #[test]
+    fn gc_pushes_the_number_of_collected_objects() {
+        let mut m = machine();
+        let kept = m.heap.allocate(1, 0).unwrap();
+        let child = m.heap.allocate(0, 0).unwrap();
+        let detached = m.heap.allocate(0, 0).unwrap();
+        m.heap.set_child(&kept, 0, Some(&child)).unwrap();
+        m.heap.decrement_stack_references(child.clone()).unwrap();
+        m.heap.decrement_stack_references(detached.clone()).unwrap();
+        m.execute(GC).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 1);
+        assert!(matches!(m.heap.get_data_slice(&detached, 0, 0), Err(HeapError::ObjectNotFound)));
+        assert!(m.heap.get_data_slice(&child, 0, 0).is_ok());
+        m.execute(GC).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 0);
+    }
+
+    #[test]
+    fn mov_with_a_huge_start_fails_cleanly() {
+        let mut m = machine();
+        let obj_ref = m.heap.allocate(0, 8).unwrap();
+        m.heap.increment_stack_references(&obj_ref).unwrap();
+        m.stack.push_u64(42).unwrap();
+        m.stack.push_u64(u64::MAX).unwrap();
+        m.stack.push_u64(obj_ref.clone().into()).unwrap();
+        assert!(matches!(m.execute(MOV_ST_HP_8), Err(MachineError::Heap(HeapError::OutOfBoundsObjectDataAccess))));
+        m.stack.push_u64(u64::MAX - 3).unwrap();
+        m.stack.push_u64(obj_ref.into()).unwrap();
+        assert!(matches!(m.execute(MOV_HP_ST_8), Err(MachineError::Heap(HeapError::OutOfBoundsObjectDataAccess))));
+    }
+
+    #[test]
+    fn machines_run_on_a_bounded_stack() {
+        let mut m = machine().with_stack(Box::new(BoundedStack::new(Vec::new(), 8)));
+        m.execute(PSH_4([1; 4])).unwrap();
+        m.execute(DUP_4).unwrap();
+        assert!(matches!(m.execute(DUP_4), Err(MachineError::Stack(StackError::Overflow))));
+        assert_eq!(m.stack().size(), 8);
+    }
+
+    fn add_u8_max_and_one(arithmetic_mode: ArithmeticMode) -> Result<u8, MachineError> {
+        let mut m = machine().with_arithmetic_mode(arithmetic_mode);
+        m.stack.push_u8(1).unwrap();
+        m.stack.push_u8(u8::MAX).unwrap();
+        m.execute(ADD_1)?;
+        Ok(m.stack.pop_u8().unwrap())
+    }
+
+    #[test]
+    fn arithmetic_modes_handle_overflow() {
+        assert_eq!(add_u8_max_and_one(ArithmeticMode::Wrapping).unwrap(), 0);
+        assert_eq!(add_u8_max_and_one(ArithmeticMode::Saturating).unwrap(), u8::MAX);
+        assert!(matches!(add_u8_max_and_one(ArithmeticMode::Checked), Err(MachineError::ArithmeticOverflow(ADD_1))));
+        let mut m = machine().with_arithmetic_mode(ArithmeticMode::Checked);
+        m.stack.push_u64(3).unwrap();
+        m.stack.push_u64(2).unwrap();
+        assert!(matches!(m.execute(SUB_8), Err(MachineError::ArithmeticOverflow(SUB_8))));
+        let mut m = machine().with_arithmetic_mode(ArithmeticMode::Saturating);
+        m.stack.push_u16(300).unwrap();
+        m.stack.push_u16(300).unwrap();
+        m.execute(MUL_2).unwrap();
+        assert_eq!(m.stack.pop_u16().unwrap(), u16::MAX);
+    }
+
+    #[test]
+    fn machines_wrap_by_default() {
+        let mut m = machine();
+        m.stack.push_u32(1).unwrap();
+        m.stack.push_u32(0).unwrap();
+        m.execute(SUB_4).unwrap();
+        assert_eq!(m.stack.pop_u32().unwrap(), u32::MAX);
+    }
+
+    #[test]
+    fn neg_and_abs_flip_and_clear_the_sign() {
+        let mut m = machine();
+        m.stack.push_f32(1.5).unwrap();
+        m.execute(NEG_F_4).unwrap();
+        assert_eq!(m.stack.peek_f32().unwrap(), -1.5);
+        m.execute(ABS_F_4).unwrap();
+        assert_eq!(m.stack.pop_f32().unwrap(), 1.5);
+        m.stack.push_f64(0.0).unwrap();
+        m.execute(NEG_F_8).unwrap();
+        assert_eq!(m.stack.peek_f64().unwrap().to_bits(), (-0.0f64).to_bits());
+        m.execute(ABS_F_8).unwrap();
+        assert_eq!(m.stack.pop_f64().unwrap().to_bits(), 0.0f64.to_bits());
+    }
+
+    #[test]
+    fn neg_and_abs_keep_nan_payloads() {
+        let mut m = machine();
+        let nan = f64::from_bits(0x7FF8_0000_0000_1234);
+        m.stack.push_f64(nan).unwrap();
+        m.execute(NEG_F_8).unwrap();
+        assert_eq!(m.stack.peek_f64().unwrap().to_bits(), 0xFFF8_0000_0000_1234);
+        m.execute(ABS_F_8).unwrap();
+        assert_eq!(m.stack.pop_f64().unwrap().to_bits(), nan.to_bits());
+        m.stack.push_f32(-f32::NAN).unwrap();
+        m.execute(ABS_F_4).unwrap();
+        assert_eq!(m.stack.pop_f32().unwrap().to_bits(), f32::NAN.to_bits());
+    }
+
+    #[test]
+    fn min_and_max_order_by_signedness() {
+        let mut m = machine();
+        let cases = [(MIN_U_1, 1), (MAX_U_1, 0xFF), (MIN_S_1, 0xFF), (MAX_S_1, 1)];
+        for (instruction, expected) in cases {
+            m.stack.push_u8(0xFF).unwrap();
+            m.stack.push_u8(1).unwrap();
+            m.execute(instruction).unwrap();
+            assert_eq!(m.stack.pop_u8().unwrap(), expected, "{:?}", instruction);
+        }
+        m.stack.push_i64(-5).unwrap();
+        m.stack.push_i64(3).unwrap();
+        m.execute(MIN_S_8).unwrap();
+        assert_eq!(m.stack.pop_i64().unwrap(), -5);
+        m.stack.push_u64(-5i64 as u64).unwrap();
+        m.stack.push_u64(3).unwrap();
+        m.execute(MIN_U_8).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 3);
+        m.stack.push_u16(0x8000).unwrap();
+        m.stack.push_u16(0x7FFF).unwrap();
+        m.execute(MAX_S_2).unwrap();
+        assert_eq!(m.stack.pop_u16().unwrap(), 0x7FFF);
+        m.stack.push_u32(0x8000_0000).unwrap();
+        m.stack.push_u32(0x7FFF_FFFF).unwrap();
+        m.execute(MAX_U_4).unwrap();
+        assert_eq!(m.stack.pop_u32().unwrap(), 0x8000_0000);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn rotations_wrap_around_the_full_width() {
+        let mut m = machine();
+        m.stack.push_u8(3).unwrap();
+        m.stack.push_u8(0b1010_0001).unwrap();
+        m.execute(ROL_1).unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), 0b0000_1101);
+        m.stack.push_u8(1).unwrap();
+        m.stack.push_u16(0x0001).unwrap();
+        m.execute(ROR_2).unwrap();
+        assert_eq!(m.stack.pop_u16().unwrap(), 0x8000);
+        // the count is taken modulo the bit width
+        m.stack.push_u8(36).unwrap();
+        m.stack.push_u32(0x1234_5678).unwrap();
+        m.execute(ROL_4).unwrap();
+        assert_eq!(m.stack.pop_u32().unwrap(), 0x2345_6781);
+        m.stack.push_u8(64).unwrap();
+        m.stack.push_u64(0xDEAD_BEEF).unwrap();
+        m.execute(ROR_8).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 0xDEAD_BEEF);
+        m.stack.push_u8(255).unwrap();
+        m.stack.push_u64(1).unwrap();
+        m.execute(ROL_8).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 1 << 63);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn bit_counts_of_zero_one_bit_and_all_bits() {
+        let mut m = machine();
+        let cases = [
+            (CLZ_1, 0u64, 8), (CTZ_1, 0, 8), (POPCNT_1, 0, 0),
+            (CLZ_1, 0x10, 3), (CTZ_1, 0x10, 4), (POPCNT_1, 0x10, 1),
+            (CLZ_1, 0xFF, 0), (CTZ_1, 0xFF, 0), (POPCNT_1, 0xFF, 8),
+            (CLZ_2, 0, 16), (CTZ_2, 0x0100, 8), (POPCNT_2, 0xFFFF, 16),
+            (CLZ_4, 0, 32), (CTZ_4, 0x8000_0000, 31), (POPCNT_4, u32::MAX as u64, 32),
+            (CLZ_8, 0, 64), (CLZ_8, 1, 63), (CTZ_8, 0, 64), (POPCNT_8, u64::MAX, 64)
+        ];
+        for (instruction, value, expected) in cases {
+            let width = match instruction {
+                CLZ_1 | CTZ_1 | POPCNT_1 => 1,
+                CLZ_2 | CTZ_2 | POPCNT_2 => 2,
+                CLZ_4 | CTZ_4 | POPCNT_4 => 4,
+                _ => 8
+            };
+            m.stack.push_slice(&value.to_le_bytes()[..width]).unwrap();
+            m.execute(instruction).unwrap();
+            let mut result = [0; 8];
+            result[..width].copy_from_slice(&m.stack.pop_slice(width).unwrap());
+            assert_eq!(u64::from_le_bytes(result), expected, "{:?} of {:#x}", instruction, value);
+        }
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn load_and_store_a_mid_stack_slot() {
+        let mut m = machine();
+        m.stack.push_u64(11).unwrap();
+        m.stack.push_u64(22).unwrap();
+        m.stack.push_u64(33).unwrap();
+        m.stack.push_u64(8).unwrap();
+        m.execute(LOAD_8).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 22);
+        m.stack.push_u64(99).unwrap();
+        m.stack.push_u64(8).unwrap();
+        m.execute(STORE_8).unwrap();
+        assert_eq!(m.stack.size(), 24);
+        assert_eq!(m.stack.pop_u64().unwrap(), 33);
+        assert_eq!(m.stack.pop_u64().unwrap(), 99);
+        assert_eq!(m.stack.pop_u64().unwrap(), 11);
+        m.stack.push_u64(1).unwrap();
+        assert!(matches!(m.execute(LOAD_8), Err(MachineError::Stack(StackError::Underflow))));
+    }
+
+    #[test]
+    fn nested_frame_calls_return_in_order() {
+        let program = crate::assembler::assemble("
+            PSH_8 outer
+            CALL_FRAME
+            PSH_1 3
+            PSH_8 end
+            RET
+        outer:
+            PSH_1 1
+            PSH_8 inner
+            CALL_FRAME
+            PSH_1 2
+            RET_FRAME
+        inner:
+            PSH_1 0
+            RET_FRAME
+        end:
+        ").unwrap();
+        let mut m = Machine::new(Box::new(Cursor::new(program)));
+        m.run().unwrap();
+        assert!(m.call_stack.is_empty());
+        assert_eq!(m.stack.pop_slice(4).unwrap().as_ref(), &[1, 0, 2, 3]);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn returning_past_the_bottom_frame_fails() {
+        let mut m = machine();
+        m.stack.push_u64(5).unwrap();
+        assert!(matches!(m.execute(RET_FRAME), Err(MachineError::FrameError)));
+        // the operand stack is never taken as a return address
+        assert_eq!(m.stack.pop_u64().unwrap(), 5);
+    }
+
+    #[test]
+    fn machine_errors_display_readably() {
+        assert_eq!(
+            MachineError::UnknownInstruction { opcode: 250, offset: 16 }.to_string(),
+            "unknown instruction opcode 250 at offset 0x10"
+        );
+        assert_eq!(
+            MachineError::IncompleteInstruction { opcode: 3, offset: 0 }.to_string(),
+            "instruction with opcode 3 at offset 0x0 is missing operand bytes"
+        );
+        assert_eq!(MachineError::EndOfInstructions.to_string(), "reached the end of the instructions");
+        assert_eq!(MachineError::UnknownHostFunction(9).to_string(), "no host function is registered with id 9");
+        assert_eq!(MachineError::ArithmeticOverflow(ADD_8).to_string(), "arithmetic overflow during ADD_8");
+        assert_eq!(MachineError::FrameError.to_string(), "returned with an empty call stack");
+        assert_eq!(
+            MachineError::InvalidInstruction(InvalidInstruction::InvalidComparisonByte(7)).to_string(),
+            "invalid comparison byte 7, expected one of 0, 1, 2 or 3"
+        );
+    }
+
+    #[test]
+    fn machine_error_sources_chain_to_io_errors() {
+        let e = MachineError::from(io::Error::new(io::ErrorKind::UnexpectedEof, "short read"));
+        assert_eq!(e.to_string(), "io error: short read");
+        assert_eq!(e.source().unwrap().to_string(), "short read");
+        assert!(MachineError::StepLimitExceeded.source().is_none());
+        let boxed: Box<dyn Error> = Box::new(MachineError::EndOfInstructions);
+        assert_eq!(boxed.to_string(), "reached the end of the instructions");
+    }
+
+    #[test]
+    fn machine_error_sources_chain_to_stack_and_heap_errors() {
+        let e = MachineError::from(StackError::Underflow);
+        assert_eq!(e.to_string(), "stack error: stack underflow");
+        assert_eq!(e.source().unwrap().to_string(), "stack underflow");
+        let e = MachineError::from(HeapError::ObjectNotFound);
+        assert_eq!(e.to_string(), "heap error: object not found");
+        assert_eq!(e.source().unwrap().to_string(), "object not found");
+    }
+
+    #[test]
+    fn trace_hook_sees_each_instruction_and_stack() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut m = machine_with(|i| {
+            i.instruct(PSH_1([1])).instruct(DUP_1).instruct(ADD_1);
+        });
+        let traced = Rc::new(RefCell::new(Vec::new()));
+        let hook_traced = traced.clone();
+        m.set_trace(move |instruction, stack| hook_traced.borrow_mut().push((*instruction, stack.size())));
+        m.run().unwrap();
+        assert_eq!(*traced.borrow(), vec![(PSH_1([1]), 0), (DUP_1, 1), (ADD_1, 2)]);
+        assert_eq!(m.stack.pop_u8().unwrap(), 2);
+    }
+
+    #[test]
+    fn counts_steps_and_profiles_opcodes() {
+        let mut m = machine_with(|i| {
+            i.instruct(PSH_8(0u64.to_le_bytes()))
+                .instruct(PSH_8(3u64.to_le_bytes()))
+                .instruct(ADD_8)
+                .instruct(PSH_8(4u64.to_le_bytes()))
+                .instruct(ADD_8)
+                .instruct(POP_8);
+        });
+        m.step().unwrap();
+        assert!(m.histogram().is_none());
+        m.enable_profiling();
+        m.run().unwrap();
+        assert_eq!(m.steps_executed(), 6);
+        let histogram = m.histogram().unwrap();
+        // the first push was stepped before profiling was enabled
+        assert_eq!(histogram.get(&3), Some(&2));
+        assert_eq!(histogram.get(&ADD_8.opcode()), Some(&2));
+        assert_eq!(histogram.get(&7), Some(&1));
+        assert_eq!(histogram.len(), 3);
+    }
+
+    #[test]
+    fn halt_stops_run_before_the_following_instructions() {
+        let mut m = machine_with(|i| {
+            i.instruct(PSH_1([7])).instruct(HALT).instruct(PSH_1([8]));
+        });
+        assert!(matches!(m.run(), Err((MachineError::Halted, Some(HALT)))));
+        assert_eq!(m.stack.pop_u8().unwrap(), 7);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn halt_halts_again_on_later_steps() {
+        let mut m = machine_with(|i| {
+            i.instruct(PSH_1([7])).instruct(HALT).instruct(PSH_1([8])).instruct(POP_8);
+        });
+        assert!(matches!(m.run(), Err((MachineError::Halted, Some(HALT)))));
+        assert!(matches!(m.run(), Err((MachineError::Halted, Some(HALT)))));
+        assert!(matches!(m.step(), Err((MachineError::Halted, Some(HALT)))));
+        assert_eq!(m.stack.pop_u8().unwrap(), 7);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn cmp_ref_compares_identity() {
+        let mut m = machine();
+        let a = m.heap.allocate(0, 0).unwrap();
+        let b = m.heap.allocate(0, 0).unwrap();
+        for (first, second, expected) in [
+            (Some(&a), Some(&a), PossiblyOrdering::Equal),
+            (None, Some(&a), PossiblyOrdering::Unordered),
+            (Some(&b), None, PossiblyOrdering::Unordered)
+        ] {
+            for obj_ref in [second, first] {
+                match obj_ref {
+                    Some(obj_ref) => {
+                        m.heap.increment_stack_references(obj_ref).unwrap();
+                        m.stack.push_u64(obj_ref.clone().into()).unwrap();
+                    }
+                    None => m.stack.push_u64(0).unwrap()
+                }
+            }
+            m.execute(CMP_REF).unwrap();
+            assert_eq!(m.stack.pop_u8().unwrap(), expected as u8);
+        }
+        m.heap.increment_stack_references(&a).unwrap();
+        m.stack.push_u64(a.clone().into()).unwrap();
+        m.heap.increment_stack_references(&b).unwrap();
+        m.stack.push_u64(b.clone().into()).unwrap();
+        m.execute(CMP_REF).unwrap();
+        assert!(PossiblyOrdering::try_from(m.stack.pop_u8().unwrap()).unwrap().is_ne());
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn is_null_checks_for_the_null_reference() {
+        let mut m = machine();
+        m.stack.push_u64(0).unwrap();
+        m.execute(IS_NULL).unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), 1);
+        let obj_ref = m.heap.allocate(0, 0).unwrap();
+        m.heap.increment_stack_references(&obj_ref).unwrap();
+        m.stack.push_u64(obj_ref.into()).unwrap();
+        m.execute(IS_NULL).unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), 0);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn obj_len_instructions_report_the_object_shape() {
+        let mut m = machine();
+        let obj_ref = m.heap.allocate(3, 5).unwrap();
+        m.heap.increment_stack_references(&obj_ref).unwrap();
+        m.stack.push_u64(obj_ref.clone().into()).unwrap();
+        m.execute(OBJ_CHILDREN_LEN).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 3);
+        m.heap.increment_stack_references(&obj_ref).unwrap();
+        m.stack.push_u64(obj_ref.into()).unwrap();
+        m.execute(OBJ_DATA_LEN).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 5);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn div_rem_pushes_the_remainder_on_top_of_the_quotient() {
+        let mut m = machine();
+        m.stack.push_u8(3).unwrap();
+        m.stack.push_u8(17).unwrap();
+        m.execute(DIV_REM_U_1).unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), 2);
+        assert_eq!(m.stack.pop_u8().unwrap(), 5);
+        m.stack.push_u16(3).unwrap();
+        m.stack.push_u16(17).unwrap();
+        m.execute(DIV_REM_U_2).unwrap();
+        assert_eq!(m.stack.pop_u16().unwrap(), 2);
+        assert_eq!(m.stack.pop_u16().unwrap(), 5);
+        m.stack.push_u32(3).unwrap();
+        m.stack.push_u32(17).unwrap();
+        m.execute(DIV_REM_U_4).unwrap();
+        assert_eq!(m.stack.pop_u32().unwrap(), 2);
+        assert_eq!(m.stack.pop_u32().unwrap(), 5);
+        m.stack.push_u64(3).unwrap();
+        m.stack.push_u64(17).unwrap();
+        m.execute(DIV_REM_U_8).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 2);
+        assert_eq!(m.stack.pop_u64().unwrap(), 5);
+        m.stack.push_i8(3).unwrap();
+        m.stack.push_i8(-17).unwrap();
+        m.execute(DIV_REM_S_1).unwrap();
+        assert_eq!(m.stack.pop_i8().unwrap(), -2);
+        assert_eq!(m.stack.pop_i8().unwrap(), -5);
+        m.stack.push_i16(3).unwrap();
+        m.stack.push_i16(-17).unwrap();
+        m.execute(DIV_REM_S_2).unwrap();
+        assert_eq!(m.stack.pop_i16().unwrap(), -2);
+        assert_eq!(m.stack.pop_i16().unwrap(), -5);
+        m.stack.push_i32(3).unwrap();
+        m.stack.push_i32(-17).unwrap();
+        m.execute(DIV_REM_S_4).unwrap();
+        assert_eq!(m.stack.pop_i32().unwrap(), -2);
+        assert_eq!(m.stack.pop_i32().unwrap(), -5);
+        m.stack.push_i64(3).unwrap();
+        m.stack.push_i64(-17).unwrap();
+        m.execute(DIV_REM_S_8).unwrap();
+        assert_eq!(m.stack.pop_i64().unwrap(), -2);
+        assert_eq!(m.stack.pop_i64().unwrap(), -5);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn div_rem_by_zero() {
+        let mut m = machine();
+        m.stack.push_u32(0).unwrap();
+        m.stack.push_u32(17).unwrap();
+        m.execute(DIV_REM_U_4).unwrap();
+        assert_eq!(m.stack.pop_u32().unwrap(), 17);
+        assert_eq!(m.stack.pop_u32().unwrap(), u32::MAX);
+        m.stack.push_i32(-1).unwrap();
+        m.stack.push_i32(i32::MIN).unwrap();
+        m.execute(DIV_REM_S_4).unwrap();
+        assert_eq!(m.stack.pop_i32().unwrap(), 0);
+        assert_eq!(m.stack.pop_i32().unwrap(), i32::MIN);
+    }
+
+    #[test]
+    fn top_of_stack_is_the_left_operand() {
+        // pushing b and then a, as documented on Instruction
+        let mut m = machine();
+        m.stack.push_u32(2).unwrap();
+        m.stack.push_u32(7).unwrap();
+        m.execute(SUB_4).unwrap();
+        assert_eq!(m.stack.pop_u32().unwrap(), 5);
+        m.stack.push_f32(2.0).unwrap();
+        m.stack.push_f32(7.0).unwrap();
+        m.execute(DIV_F_4).unwrap();
+        assert_eq!(m.stack.pop_f32().unwrap(), 3.5);
+        m.stack.push_f32(7.0).unwrap();
+        m.stack.push_f32(2.0).unwrap();
+        m.execute(SUB_F_4).unwrap();
+        assert_eq!(m.stack.pop_f32().unwrap(), -5.0);
+        m.stack.push_u8(3).unwrap();
+        m.stack.push_u32(1).unwrap();
+        m.execute(SHL_4).unwrap();
+        assert_eq!(m.stack.pop_u32().unwrap(), 8);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn mov_st_hp_n_copies_the_data_in_order() {
+        let mut m = machine();
+        let obj_ref = m.heap.allocate(0, 20).unwrap();
+        m.heap.increment_stack_references(&obj_ref).unwrap();
+        let data: Vec<u8> = (1..=16).collect();
+        m.stack.push_slice(&data).unwrap();
+        m.stack.push_u64(16).unwrap();
+        m.stack.push_u64(2).unwrap();
+        m.stack.push_u64(obj_ref.clone().into()).unwrap();
+        m.execute(MOV_ST_HP_N).unwrap();
+        assert_eq!(m.stack.size(), 0);
+        assert_eq!(m.heap.get_data_slice(&obj_ref, 0, 20).unwrap(), &[0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 0, 0]);
+    }
+
+    #[test]
+    fn mov_st_hp_n_out_of_range_leaves_the_data() {
+        let mut m = machine();
+        let obj_ref = m.heap.allocate(0, 8).unwrap();
+        for (start, length) in [(0, 16), (1, 8), (usize::MAX, 2)] {
+            m.stack.push_slice(&[7; 16]).unwrap();
+            m.stack.push_u64(length as u64).unwrap();
+            m.stack.push_u64(start as u64).unwrap();
+            m.heap.increment_stack_references(&obj_ref).unwrap();
+            m.stack.push_u64(obj_ref.clone().into()).unwrap();
+            assert!(matches!(
+                m.execute(MOV_ST_HP_N),
+                Err(MachineError::Heap(HeapError::OutOfBoundsObjectDataAccess))
+            ), "{} bytes at {}", length, start);
+            assert_eq!(m.stack.pop_slice(16).unwrap().as_ref(), &[7; 16]);
+            assert_eq!(m.stack.size(), 0);
+        }
+        assert_eq!(m.heap.get_data_slice(&obj_ref, 0, 8).unwrap(), &[0; 8]);
+
+        // too little data on the stack leaves the object untouched
+        m.stack.push_slice(&[7; 4]).unwrap();
+        m.stack.push_u64(8).unwrap();
+        m.stack.push_u64(0).unwrap();
+        m.heap.increment_stack_references(&obj_ref).unwrap();
+        m.stack.push_u64(obj_ref.clone().into()).unwrap();
+        assert!(matches!(m.execute(MOV_ST_HP_N), Err(MachineError::Stack(StackError::Underflow))));
+        assert_eq!(m.stack.size(), 4);
+        assert_eq!(m.heap.get_data_slice(&obj_ref, 0, 8).unwrap(), &[0; 8]);
+    }
+
+    #[test]
+    fn mov_hp_hp_pops_its_operands_in_order() {
+        let mut m = machine();
+        let source = m.heap.allocate(0, 4).unwrap();
+        let destination = m.heap.allocate(0, 4).unwrap();
+        m.heap.get_mut_data_slice(&source, 0, 4).unwrap().copy_from_slice(&[1, 2, 3, 4]);
+        m.stack.push_u64(2).unwrap();
+        m.stack.push_u64(1).unwrap();
+        m.stack.push_u64(source.into()).unwrap();
+        m.stack.push_u64(0).unwrap();
+        m.stack.push_u64(destination.clone().into()).unwrap();
+        m.execute(MOV_HP_HP).unwrap();
+        assert_eq!(m.heap.get_data_slice(&destination, 0, 4).unwrap(), &[2, 3, 0, 0]);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn fill_writes_only_the_range() {
+        let mut m = machine();
+        let obj_ref = m.heap.allocate(0, 6).unwrap();
+        m.stack.push_u8(0xAA).unwrap();
+        m.stack.push_u64(3).unwrap();
+        m.stack.push_u64(2).unwrap();
+        m.stack.push_u64(obj_ref.clone().into()).unwrap();
+        m.execute(FILL).unwrap();
+        assert_eq!(m.heap.get_data_slice(&obj_ref, 0, 6).unwrap(), &[0, 0, 0xAA, 0xAA, 0xAA, 0]);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn fill_past_the_end_fails() {
+        let mut m = machine();
+        let obj_ref = m.heap.allocate(0, 6).unwrap();
+        m.stack.push_u8(0xAA).unwrap();
+        m.stack.push_u64(3).unwrap();
+        m.stack.push_u64(4).unwrap();
+        m.stack.push_u64(obj_ref.clone().into()).unwrap();
+        assert!(matches!(m.execute(FILL), Err(MachineError::Heap(HeapError::OutOfBoundsObjectDataAccess))));
+        assert_eq!(m.heap.get_data_slice(&obj_ref, 0, 6).unwrap(), &[0; 6]);
+    }
+
+    #[test]
+    fn builds_a_machine_with_its_own_heap() {
+        let mut program = Vec::new();
+        program
+            .instruct(PSH_8(8u64.to_le_bytes()))
+            .instruct(PSH_8(0u64.to_le_bytes()))
+            .instruct(ALLOC)
+            .instruct(PSH_8(32u64.to_le_bytes()))
+            .instruct(PSH_8(0u64.to_le_bytes()))
+            .instruct(ALLOC);
+        let mut m = Machine::new(Box::new(Cursor::new(program)))
+            .with_stack(Box::new(BoundedStack::new(Vec::new(), 64)))
+            .with_heap(Heap::with_limit(16));
+        assert!(matches!(m.run(), Err((MachineError::Heap(HeapError::OutOfMemory), Some(ALLOC)))));
+        assert_eq!(m.heap().object_count(), 1);
+        assert_eq!(m.stack().size(), 8);
+    }
+
+    #[test]
+    fn runs_a_machine_built_from_bytecode() {
+        let mut program = Vec::new();
+        program.instruct(PSH_2(3u16.to_le_bytes())).instruct(PSH_2(4u16.to_le_bytes())).instruct(MUL_2);
+        let mut m = Machine::from_bytecode_slice(&program);
+        m.run().unwrap();
+        assert_eq!(m.stack.pop_u16().unwrap(), 12);
+        let mut m = Machine::from_bytecode(program);
+        m.run().unwrap();
+        assert_eq!(m.stack.pop_u16().unwrap(), 12);
+    }
+
+    #[test]
+    fn jump_to_redirects_execution() {
+        let mut m = machine_with(|i| {
+            i.instruct(PSH_1([1])).instruct(PSH_1([2])).instruct(PSH_1([3]));
+        });
+        m.step().unwrap();
+        m.step().unwrap();
+        assert_eq!(m.instruction_pointer().unwrap(), 4);
+        m.jump_to(0).unwrap();
+        m.step().unwrap();
+        assert_eq!(m.instruction_pointer().unwrap(), 2);
+        assert_eq!(m.stack.pop_u8().unwrap(), 1);
+        assert_eq!(m.stack.pop_u8().unwrap(), 2);
+        assert_eq!(m.stack.pop_u8().unwrap(), 1);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn step_debug_returns_the_executed_instructions() {
+        let mut m = machine_with(|i| {
+            i.instruct(PSH_1([2])).instruct(DUP_1).instruct(ADD_1);
+        });
+        let mut executed = Vec::new();
+        loop {
+            match m.step_debug() {
+                Ok(instruction) => executed.push(instruction),
+                Err((MachineError::EndOfInstructions, None)) => break,
+                Err((e, _)) => panic!("{}", e)
+            }
+        }
+        assert_eq!(executed, [PSH_1([2]), DUP_1, ADD_1]);
+        assert_eq!(m.stack.pop_u8().unwrap(), 4);
+    }
+
+    #[test]
+    fn restore_returns_to_the_snapshot() {
+        let mut m = machine_with(|i| {
+            i
+                .instruct(PSH_8(4u64.to_le_bytes()))
+                .instruct(PSH_8(0u64.to_le_bytes()))
+                .instruct(ALLOC)
+                .instruct(PSH_1([1]))
+                .instruct(PSH_1([2]))
+                .instruct(POP_1);
+        });
+        m.step().unwrap();
+        m.step().unwrap();
+        m.step().unwrap();
+        let snapshot = m.snapshot().unwrap();
+        let expected_stack = m.stack.read_at(0, m.stack.size()).unwrap().to_vec();
+        let obj_ref = ObjectReference::new_result(m.stack.peek_u64().unwrap()).unwrap();
+        m.heap.get_mut_data_slice(&obj_ref, 0, 4).unwrap().copy_from_slice(&[9; 4]);
+        m.step().unwrap();
+        m.step().unwrap();
+        m.restore(snapshot).unwrap();
+        assert_eq!(m.stack.read_at(0, m.stack.size()).unwrap(), expected_stack);
+        assert_eq!(m.heap.get_data_slice(&obj_ref, 0, 4).unwrap(), &[0; 4]);
+        assert_eq!(m.step_debug().unwrap(), PSH_1([1]));
+    }
+
+    #[test]
+    fn snapshots_are_unaffected_by_later_changes() {
+        let mut m = machine();
+        let obj_ref = m.heap.allocate(0, 1).unwrap();
+        m.heap.get_mut_data_slice(&obj_ref, 0, 1).unwrap()[0] = 5;
+        m.stack.push_u64(obj_ref.clone().into()).unwrap();
+        let snapshot = m.snapshot().unwrap();
+        m.heap.get_mut_data_slice(&obj_ref, 0, 1).unwrap()[0] = 6;
+        m.stack.push_u8(1).unwrap();
+        m.restore(snapshot).unwrap();
+        assert_eq!(m.heap.get_data_slice(&obj_ref, 0, 1).unwrap(), &[5]);
+        assert_eq!(m.stack.pop_u64().unwrap(), u64::from(obj_ref));
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn runs_out_of_fuel_at_the_expected_step() {
+        let program = |i: &mut Vec<u8>| {
+            i.instruct(PSH_1([1])).instruct(PSH_1([2])).instruct(PSH_1([3]));
+        };
+        let mut m = machine_with(program);
+        m.set_fuel(2);
+        assert!(matches!(m.run(), Err((MachineError::OutOfFuel, Some(PSH_1([3]))))));
+        assert_eq!(m.steps_executed(), 2);
+        assert_eq!(m.remaining_fuel(), Some(0));
+        assert_eq!(m.stack.size(), 2);
+
+        let mut m = machine_with(program);
+        m.run().unwrap();
+        assert_eq!(m.remaining_fuel(), None);
+        assert_eq!(m.stack.size(), 3);
+    }
+
+    #[test]
+    fn heap_operations_consume_more_fuel() {
+        let mut arithmetic = machine_with(|i| {
+            for _ in 0..10 {
+                i.instruct(PSH_1([1])).instruct(PSH_1([2])).instruct(ADD_1).instruct(POP_1);
+            }
+        });
+        arithmetic.set_fuel(50);
+        arithmetic.run().unwrap();
+        let mut allocating = machine_with(|i| {
+            for _ in 0..10 {
+                i.instruct(PSH_8(0u64.to_le_bytes())).instruct(PSH_8(0u64.to_le_bytes())).instruct(ALLOC).instruct(POP_8);
+            }
+        });
+        allocating.set_fuel(50);
+        assert!(matches!(allocating.run(), Err((MachineError::OutOfFuel, Some(ALLOC)))));
+        assert!(allocating.steps_executed() < arithmetic.steps_executed());
+
+        // the instruction is stepped again once refueled
+        allocating.set_fuel(10);
+        assert_eq!(allocating.step_debug().unwrap(), ALLOC);
+    }
+
+    #[test]
+    fn gas_cost_can_be_replaced() {
+        let mut m = machine_with(|i| {
+            i.instruct(PSH_1([1])).instruct(PSH_1([2]));
+        });
+        m.set_fuel(5).set_gas_cost(|_| 3);
+        assert!(matches!(m.run(), Err((MachineError::OutOfFuel, Some(PSH_1([2]))))));
+        assert_eq!(m.remaining_fuel(), Some(2));
+    }
+
+    #[test]
+    fn float_to_integer_conversions_saturate() {
+        let mut m = machine();
+        for (value, unsigned, signed) in [
+            (f64::NAN, 0, 0),
+            (f64::INFINITY, u64::MAX, i64::MAX),
+            (f64::NEG_INFINITY, 0, i64::MIN),
+            (-1.5, 0, -1),
+            (1e30, u64::MAX, i64::MAX),
+            (-1e30, 0, i64::MIN),
+            (42.9, 42, 42)
+        ] {
+            m.stack.push_f32(value as f32).unwrap();
+            m.execute(CNV_F4_U8).unwrap();
+            assert_eq!(m.stack.pop_u64().unwrap(), unsigned, "CNV_F4_U8 {}", value);
+            m.stack.push_f64(value).unwrap();
+            m.execute(CNV_F8_U8).unwrap();
+            assert_eq!(m.stack.pop_u64().unwrap(), unsigned, "CNV_F8_U8 {}", value);
+            m.stack.push_f32(value as f32).unwrap();
+            m.execute(CNV_F4_S8).unwrap();
+            assert_eq!(m.stack.pop_i64().unwrap(), signed, "CNV_F4_S8 {}", value);
+            m.stack.push_f64(value).unwrap();
+            m.execute(CNV_F8_S8).unwrap();
+            assert_eq!(m.stack.pop_i64().unwrap(), signed, "CNV_F8_S8 {}", value);
+        }
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn runs_on_a_cursor_and_array_stack() {
+        let mut program = Vec::new();
+        program
+            .instruct(PSH_8(0u64.to_le_bytes())) // total
+            .instruct(PSH_8(3u64.to_le_bytes())) // counter
+            .instruct(SWAP_8) // address 18
+            .instruct(PSH_8(2u64.to_le_bytes()))
+            .instruct(ADD_8)
+            .instruct(SWAP_8)
+            .instruct(PSH_8(1u64.to_le_bytes()))
+            .instruct(SWAP_8)
+            .instruct(SUB_8)
+            .instruct(DUP_8)
+            .instruct(PSH_8(18u64.to_le_bytes()))
+            .instruct(SWAP_8)
+            .instruct(CNV_U8_U1)
+            .instruct(JMP_IF);
+        let mut m: Machine<Cursor<Vec<u8>>, ArrayStack<32>> =
+            Machine::from_parts(Cursor::new(program), ArrayStack::new());
+        m.run().unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 0);
+        assert_eq!(m.stack.pop_u64().unwrap(), 6);
+        assert_eq!(m.stack.size(), 0);
+
+        let mut program = Vec::new();
+        for _ in 0..5 {
+            program.instruct(PSH_8(1u64.to_le_bytes()));
+        }
+        let mut m: Machine<Cursor<Vec<u8>>, ArrayStack<32>> =
+            Machine::from_parts(Cursor::new(program), ArrayStack::new());
+        assert!(matches!(m.run(), Err((MachineError::Stack(StackError::Overflow), Some(PSH_8(_))))));
+        assert_eq!(m.stack.size(), 32);
+    }
+
+    #[test]
+    fn reserve_stack_avoids_reallocation() {
+        let mut m: Machine<Cursor<Vec<u8>>, Vec<u8>> = Machine::from_parts(Cursor::new(Vec::new()), Vec::new());
+        m.reserve_stack(40);
+        let (pointer, capacity) = (m.stack.as_ptr(), m.stack.capacity());
+        for i in 0..5u64 {
+            m.execute(PSH_8(i.to_le_bytes())).unwrap();
+        }
+        assert_eq!(m.stack.as_ptr(), pointer);
+        assert_eq!(m.stack.capacity(), capacity);
+    }
+
+    #[test]
+    fn extends_integer_widths() {
+        let mut m = machine();
+        m.stack.push_u8(0xFF).unwrap();
+        m.execute(CNV_U1_U8).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 255);
+        m.stack.push_u8(0xFF).unwrap();
+        m.execute(CNV_S1_S8).unwrap();
+        assert_eq!(m.stack.pop_i64().unwrap(), -1);
+        m.stack.push_u16(0xFFFF).unwrap();
+        m.execute(CNV_U2_U8).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 0xFFFF);
+        m.stack.push_u16(0xFFFF).unwrap();
+        m.execute(CNV_S2_S8).unwrap();
+        assert_eq!(m.stack.pop_i64().unwrap(), -1);
+        m.stack.push_u32(0xFFFF_FFFF).unwrap();
+        m.execute(CNV_U4_U8).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 0xFFFF_FFFF);
+        m.stack.push_u32(0xFFFF_FFFF).unwrap();
+        m.execute(CNV_S4_S8).unwrap();
+        assert_eq!(m.stack.pop_i64().unwrap(), -1);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn truncates_integer_widths() {
+        let mut m = machine();
+        m.stack.push_u64(0x1122_3344_5566_7788).unwrap();
+        m.execute(CNV_U8_U1).unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), 0x88);
+        m.stack.push_u64(0x1122_3344_5566_7788).unwrap();
+        m.execute(CNV_U8_U2).unwrap();
+        assert_eq!(m.stack.pop_u16().unwrap(), 0x7788);
+        m.stack.push_u64(0x1122_3344_5566_7788).unwrap();
+        m.execute(CNV_U8_U4).unwrap();
+        assert_eq!(m.stack.pop_u32().unwrap(), 0x5566_7788);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn float_library_functions() {
+        let mut m = machine();
+        m.stack.push_f32(4.0).unwrap();
+        m.execute(SQRT_F_4).unwrap();
+        assert_eq!(m.stack.pop_f32().unwrap(), 2.0);
+        m.stack.push_f64(-4.0).unwrap();
+        m.execute(SQRT_F_8).unwrap();
+        assert!(m.stack.pop_f64().unwrap().is_nan());
+        m.stack.push_f32(0.0).unwrap();
+        m.execute(SIN_F_4).unwrap();
+        assert_eq!(m.stack.pop_f32().unwrap(), 0.0);
+        m.stack.push_f64(0.0).unwrap();
+        m.execute(SIN_F_8).unwrap();
+        assert_eq!(m.stack.pop_f64().unwrap(), 0.0);
+        m.stack.push_f32(0.0).unwrap();
+        m.execute(COS_F_4).unwrap();
+        assert_eq!(m.stack.pop_f32().unwrap(), 1.0);
+        m.stack.push_f64(std::f64::consts::PI).unwrap();
+        m.execute(COS_F_8).unwrap();
+        assert_eq!(m.stack.pop_f64().unwrap(), -1.0);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn fma_rounds_once() {
+        let mut m = machine();
+        // 0.1 * 10 rounds to exactly 1 when multiplied on its own, losing the error of 0.1
+        m.stack.push_f32(-1.0).unwrap();
+        m.stack.push_f32(10.0).unwrap();
+        m.stack.push_f32(0.1).unwrap();
+        m.execute(FMA_F_4).unwrap();
+        let fused = m.stack.pop_f32().unwrap();
+        m.stack.push_f32(-1.0).unwrap();
+        m.stack.push_f32(10.0).unwrap();
+        m.stack.push_f32(0.1).unwrap();
+        m.execute(MUL_F_4).unwrap();
+        m.execute(ADD_F_4).unwrap();
+        assert_eq!(m.stack.pop_f32().unwrap(), 0.0);
+        assert_eq!(fused, 0.1f32.mul_add(10.0, -1.0));
+        assert_ne!(fused, 0.0);
+
+        m.stack.push_f64(-1.0).unwrap();
+        m.stack.push_f64(10.0).unwrap();
+        m.stack.push_f64(0.1).unwrap();
+        m.execute(FMA_F_8).unwrap();
+        let fused = m.stack.pop_f64().unwrap();
+        m.stack.push_f64(-1.0).unwrap();
+        m.stack.push_f64(10.0).unwrap();
+        m.stack.push_f64(0.1).unwrap();
+        m.execute(MUL_F_8).unwrap();
+        m.execute(ADD_F_8).unwrap();
+        assert_eq!(m.stack.pop_f64().unwrap(), 0.0);
+        assert_eq!(fused, 0.1f64.mul_add(10.0, -1.0));
+        assert_ne!(fused, 0.0);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn classifies_floats() {
+        let mut m = machine();
+        for (value, nan, inf) in [(f64::NAN, 1, 0), (f64::INFINITY, 0, 1), (f64::NEG_INFINITY, 0, 1), (1.5, 0, 0)] {
+            m.stack.push_f32(value as f32).unwrap();
+            m.execute(IS_NAN_4).unwrap();
+            assert_eq!(m.stack.pop_u8().unwrap(), nan, "IS_NAN_4 {}", value);
+            m.stack.push_f64(value).unwrap();
+            m.execute(IS_NAN_8).unwrap();
+            assert_eq!(m.stack.pop_u8().unwrap(), nan, "IS_NAN_8 {}", value);
+            m.stack.push_f32(value as f32).unwrap();
+            m.execute(IS_INF_4).unwrap();
+            assert_eq!(m.stack.pop_u8().unwrap(), inf, "IS_INF_4 {}", value);
+            m.stack.push_f64(value).unwrap();
+            m.execute(IS_INF_8).unwrap();
+            assert_eq!(m.stack.pop_u8().unwrap(), inf, "IS_INF_8 {}", value);
+        }
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn float_comparison_is_a_total_order() {
+        let mut m = machine();
+        m.stack.push_f32(1.0).unwrap();
+        m.stack.push_f32(f32::NAN).unwrap();
+        m.execute(CMP_F4).unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), PossiblyOrdering::Greater as u8);
+        m.stack.push_f32(0.0).unwrap();
+        m.stack.push_f32(-0.0).unwrap();
+        m.execute(CMP_F4).unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), PossiblyOrdering::Less as u8);
+        m.stack.push_f64(1.0).unwrap();
+        m.stack.push_f64(-f64::NAN).unwrap();
+        m.execute(CMP_F8).unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), PossiblyOrdering::Less as u8);
+        m.stack.push_f64(-0.0).unwrap();
+        m.stack.push_f64(0.0).unwrap();
+        m.execute(CMP_F8).unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), PossiblyOrdering::Greater as u8);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn jmp_imm_skips_forward() {
+        let skipped = PSH_1([2]);
+        let target = (JMP_IMM([0; 8]).encoded_len() + skipped.encoded_len()) as u64;
+        let mut m = machine_with(|i| {
+            i.instruct(JMP_IMM(target.to_le_bytes())).instruct(skipped).instruct(PSH_1([3]));
+        });
+        m.run().unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), 3);
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn jmp_if_branches_on_a_boolean_byte() {
+        for (jump, condition, taken) in [(JMP_IF, 1, true), (JMP_IF, 0, false), (JMP_IF_NOT, 0, true), (JMP_IF_NOT, 1, false)] {
+            let skipped = PSH_1([2]);
+            let target = (PSH_8([0; 8]).encoded_len() + PSH_1([0]).encoded_len() + jump.encoded_len() + skipped.encoded_len()) as u64;
+            let mut m = machine_with(|i| {
+                i.instruct(PSH_8(target.to_le_bytes())).instruct(PSH_1([condition])).instruct(jump).instruct(skipped);
+            });
+            m.run().unwrap();
+            assert_eq!(m.stack.size(), if taken { 0 } else { 1 }, "{} {}", jump, condition);
+        }
+    }
+
+    #[test]
+    fn alloc_rejects_huge_lengths() {
+        let mut m = machine();
+        m.stack.push_u64(0).unwrap();
+        m.stack.push_u64(u64::MAX).unwrap();
+        assert!(matches!(m.execute(ALLOC), Err(MachineError::Heap(HeapError::Allocation))));
+        m.stack.push_u64(u64::MAX).unwrap();
+        m.stack.push_u64(0).unwrap();
+        assert!(matches!(m.execute(ALLOC), Err(MachineError::Heap(HeapError::Allocation))));
+        assert_eq!(m.heap.object_count(), 0);
+    }
+
+    #[test]
+    fn copy_ref_counts_past_u16_max() {
+        let mut m = machine();
+        let obj_ref = m.heap.allocate(0, 0).unwrap();
+        m.stack.push_u64(obj_ref.into()).unwrap();
+        for _ in 0..u16::MAX as usize + 10 {
+            m.execute(COPY_REF).unwrap();
+        }
+        assert_eq!(m.stack.size(), (u16::MAX as usize + 11) * 8);
+    }
+
+    #[test]
+    fn copy_ref_fails_cleanly_when_the_stack_overflows() {
+        let mut m = machine().with_stack(Box::new(BoundedStack::new(Vec::new(), 8)));
+        let obj_ref = m.heap.allocate(0, 0).unwrap();
+        m.stack.push_u64(obj_ref.into()).unwrap();
+        assert!(matches!(m.execute(COPY_REF), Err(MachineError::Stack(StackError::Overflow))));
+        assert_eq!(m.stack.size(), 8);
+        // the reference count is back to the single reference on the stack
+        m.execute(IS_NULL).unwrap();
+        assert_eq!(m.heap.collect_garbage().reclaimed, 1);
+    }
+
+    #[test]
+    fn auto_collect_waits_for_the_instruction_to_finish() {
+        let mut heap = Heap::new();
+        heap.set_auto_collect(true);
+        let mut m = machine().with_heap(heap);
+        let obj_ref = m.heap.allocate(0, 4).unwrap();
+        m.heap.get_mut_data_slice(&obj_ref, 0, 4).unwrap().copy_from_slice(&7u32.to_le_bytes());
+        // the only reference is popped by the MOV, which still reads the object afterwards
+        m.stack.push_u64(0).unwrap();
+        m.stack.push_u64(obj_ref.into()).unwrap();
+        m.execute(MOV_HP_ST_4).unwrap();
+        assert_eq!(m.stack.pop_u32().unwrap(), 7);
+        assert_eq!(m.heap.object_count(), 0);
+    }
+
+    fn auto_collecting_machine() -> Machine {
+        let mut heap = Heap::new();
+        heap.set_auto_collect(true);
+        machine().with_heap(heap)
+    }
+
+    #[test]
+    fn missing_objects_are_rejected_when_popped() {
+        let mut m = machine();
+        let obj_ref = m.heap.allocate(0, 0).unwrap();
+        assert!(m.heap.free(obj_ref.clone()).unwrap());
+        m.stack.push_u64(obj_ref.into()).unwrap();
+        assert!(matches!(m.execute(IS_NULL), Err(MachineError::Heap(HeapError::ObjectNotFound))));
+        assert_eq!(m.stack.size(), 0);
+    }
+
+    #[test]
+    fn auto_collect_after_get_child_pops_the_last_parent_reference() {
+        let mut m = auto_collecting_machine();
+        let parent = m.heap.allocate(1, 0).unwrap();
+        let child = m.heap.allocate(0, 0).unwrap();
+        m.heap.set_child(&parent, 0, Some(&child)).unwrap();
+        m.heap.decrement_stack_references(child.clone()).unwrap();
+        m.stack.push_u64(0).unwrap();
+        m.stack.push_u64(parent.clone().into()).unwrap();
+        m.execute(GET_CHILD).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), u64::from(child.clone()));
+        assert!(!m.heap.contains(&parent));
+        assert!(m.heap.contains(&child));
+    }
+
+    #[test]
+    fn auto_collect_after_set_child_pops_the_last_child_reference() {
+        let mut m = auto_collecting_machine();
+        let parent = m.heap.allocate(1, 0).unwrap();
+        let child = m.heap.allocate(0, 0).unwrap();
+        m.heap.increment_stack_references(&parent).unwrap();
+        m.stack.push_u64(child.clone().into()).unwrap();
+        m.stack.push_u64(0).unwrap();
+        m.stack.push_u64(parent.clone().into()).unwrap();
+        m.execute(SET_CHILD).unwrap();
+        // the child is now held by the parent, which is still referenced
+        assert!(m.heap.contains(&child));
+        assert_eq!(m.heap.get_child(&parent, 0).unwrap(), Some(child));
+        assert_eq!(m.heap.object_count(), 2);
+    }
+
+    #[test]
+    fn auto_collect_after_is_null_pops_the_last_reference() {
+        let mut m = auto_collecting_machine();
+        let obj_ref = m.heap.allocate(0, 0).unwrap();
+        m.stack.push_u64(obj_ref.clone().into()).unwrap();
+        m.execute(IS_NULL).unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), 0);
+        assert!(!m.heap.contains(&obj_ref));
+        assert_eq!(m.heap.object_count(), 0);
+    }
+
+    #[test]
+    fn machines_share_a_program() {
+        let mut program = Vec::new();
+        program.instruct(PSH_1([1])).instruct(PSH_1([2])).instruct(ADD_1);
+        let program: Arc<[u8]> = program.into();
+        let mut a = Machine::from_shared_bytecode(program.clone());
+        let mut b = Machine::from_shared_bytecode(program.clone());
+        a.step().unwrap();
+        b.step().unwrap();
+        b.step().unwrap();
+        a.step().unwrap();
+        a.step().unwrap();
+        b.step().unwrap();
+        assert_eq!(a.stack.pop_u8().unwrap(), 3);
+        assert_eq!(b.stack.pop_u8().unwrap(), 3);
+        assert_eq!(Arc::strong_count(&program), 3);
+    }
+
+    #[test]
+    fn run_block_matches_stepping() {
+        let program = |p: &mut Vec<u8>| {
+            p.instruct(PSH_8(3u64.to_le_bytes()))
+                .instruct(PSH_8(4u64.to_le_bytes()))
+                .instruct(MUL_8)
+                .instruct(DUP_8)
+                .instruct(PSH_4(2.5f32.to_le_bytes()))
+                .instruct(NEG_F_4)
+                .instruct(CNV_F4_S8)
+                .instruct(ADD_8);
+        };
+        let mut length = Vec::new();
+        program(&mut length);
+        let mut blocked = machine_with(program);
+        assert_eq!(blocked.run_block(length.len() as u64).unwrap(), BlockEnd::Reached);
+        let mut stepped = machine_with(program);
+        for _ in 0..8 {
+            stepped.step().unwrap();
+        }
+        assert_eq!(blocked.steps_executed(), stepped.steps_executed());
+        assert_eq!(blocked.instructions.stream_position().unwrap(), stepped.instructions.stream_position().unwrap());
+        assert_eq!(blocked.stack.size(), stepped.stack.size());
+        assert_eq!(blocked.stack.pop_i64().unwrap(), 10);
+        assert_eq!(stepped.stack.pop_i64().unwrap(), 10);
+        assert_eq!(blocked.stack.pop_u64().unwrap(), 12);
+        assert_eq!(stepped.stack.pop_u64().unwrap(), 12);
+    }
+
+    #[test]
+    fn run_block_stops_before_branches() {
+        let mut m = machine_with(|p| {
+            p.instruct(PSH_1([1]))
+                .instruct(HALT)
+                .instruct(POP_1);
+        });
+        assert_eq!(m.run_block(u64::MAX).unwrap(), BlockEnd::Branch);
+        assert_eq!(m.instructions.stream_position().unwrap(), 2);
+        assert_eq!(m.steps_executed(), 1);
+        assert!(matches!(m.step(), Err((MachineError::Halted, Some(HALT)))));
+    }
+
+    #[test]
+    fn run_block_reports_why_it_stopped() {
+        let mut m = machine_with(|p| {
+            p.instruct(PSH_1([1]))
+                .instruct(PSH_1([2]))
+                .instruct(POP_1);
+        });
+        m.set_fuel(1);
+        assert_eq!(m.run_block(u64::MAX).unwrap(), BlockEnd::OutOfFuel);
+        assert_eq!(m.instructions.stream_position().unwrap(), 2);
+        assert!(matches!(m.step(), Err((MachineError::OutOfFuel, Some(PSH_1([2]))))));
+
+        m.set_fuel(10);
+        assert_eq!(m.run_block(u64::MAX).unwrap(), BlockEnd::EndOfInstructions);
+        assert_eq!(m.steps_executed(), 3);
+        assert_eq!(m.stack.pop_u8().unwrap(), 1);
+        // like run, the end of the instructions is not an error
+        assert_eq!(m.run_block(u64::MAX).unwrap(), BlockEnd::EndOfInstructions);
+    }
+
+    #[test]
+    fn call_ext_n_borrows_its_arguments() {
+        let mut m = machine();
+        m.register_host_fn_n(7, |arguments, _| {
+            assert_eq!(arguments.len(), 12);
+            let a = u64::from_le_bytes(arguments[..8].try_into().unwrap());
+            let b = u32::from_le_bytes(arguments[8..].try_into().unwrap());
+            Ok(HostReturn::Bytes((a * b as u64).to_le_bytes().to_vec()))
+        });
+        m.stack.push_u8(0xAA).unwrap();
+        m.stack.push_u64(6).unwrap();
+        m.stack.push_u32(7).unwrap();
+        m.stack.push_u64(12).unwrap();
+        m.stack.push_u64(7).unwrap();
+        m.execute(CALL_EXT_N).unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), 42);
+        assert_eq!(m.stack.pop_u8().unwrap(), 0xAA);
+        assert_eq!(m.stack.size(), 0);
+
+        // ids are separate from those of CALL_EXT
+        m.register_host_fn(8, |_, _| Ok(HostReturn::None));
+        m.stack.push_u64(0).unwrap();
+        m.stack.push_u64(8).unwrap();
+        assert!(matches!(m.execute(CALL_EXT_N), Err(MachineError::UnknownHostFunction(8))));
+        m.stack.push_u64(u64::MAX).unwrap();
+        m.stack.push_u64(7).unwrap();
+        assert!(matches!(m.execute(CALL_EXT_N), Err(MachineError::Stack(StackError::Underflow))));
+    }
+
+    #[test]
+    fn host_functions_return_values_to_push() {
+        let mut m = machine();
+        m.register_host_fn(1, |_, _| Ok(HostReturn::None));
+        m.register_host_fn(2, |stack, _| Ok(HostReturn::Bytes((stack.pop_u64()? * 2).to_le_bytes().to_vec())));
+        m.stack.push_u64(1).unwrap();
+        m.execute(CALL_EXT).unwrap();
+        assert_eq!(m.stack.size(), 0);
+        m.stack.push_u64(21).unwrap();
+        m.stack.push_u64(2).unwrap();
+        m.execute(CALL_EXT).unwrap();
+        assert_eq!(m.stack.size(), 8);
+        assert_eq!(m.stack.pop_u64().unwrap(), 42);
+    }
+
+    #[test]
+    fn runs_program_files() {
+        let path = std::env::temp_dir().join(format!("bytecode_playground_{}.bcpg", std::process::id()));
+        let mut instructions = Vec::new();
+        instructions.instruct(PSH_8(5u64.to_le_bytes())).instruct(JMP_IMM(19u64.to_le_bytes())).instruct(HALT);
+        instructions.instruct(PSH_1([1]));
+        let mut file = Vec::new();
+        crate::program_file::write_header(&mut file, &instructions).unwrap();
+        file.extend_from_slice(&instructions);
+        std::fs::write(&path, &file).unwrap();
+        let mut m = Machine::from_program_file(&path).unwrap();
+        m.run().unwrap();
+        assert_eq!(m.stack.pop_u8().unwrap(), 1);
+        assert_eq!(m.stack.pop_u64().unwrap(), 5);
+
+        std::fs::write(&path, &file[..HEADER_LEN - 1]).unwrap();
+        assert!(matches!(Machine::from_program_file(&path), Err(MachineError::Integrity(IntegrityError::Truncated))));
+        std::fs::write(&path, []).unwrap();
+        assert!(matches!(Machine::from_program_file(&path), Err(MachineError::Integrity(IntegrityError::Truncated))));
+        std::fs::write(&path, &file[..file.len() - 1]).unwrap();
+        assert!(matches!(
+            Machine::from_program_file(&path),
+            Err(MachineError::Integrity(IntegrityError::ChecksumMismatch { .. }))
+        ));
+        file[0] = 0;
+        std::fs::write(&path, &file).unwrap();
+        assert!(matches!(Machine::from_program_file(&path), Err(MachineError::InvalidMagic)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn psh_null_pushes_the_null_reference() {
+        let mut m = machine_with(|p| {
+            p.instruct(PSH_NULL)
+                .instruct(IS_NULL)
+                .instruct(PSH_NULL);
+        });
+        m.run().unwrap();
+        assert_eq!(m.stack.pop_u64().unwrap(), NULL_REFERENCE);
+        assert_eq!(m.stack.pop_u8().unwrap(), 1);
+        assert_eq!(m.stack.size(), 0);
+
+        // clears a child, as a null reference pushed by PSH_8 does
+        let mut m = machine();
+        let parent = m.heap.allocate(1, 0).unwrap();
+        let child = m.heap.allocate(0, 0).unwrap();
+        m.heap.set_child(&parent, 0, Some(&child)).unwrap();
+        m.heap.increment_stack_references(&parent).unwrap();
+        m.execute(PSH_NULL).unwrap();
+        m.stack.push_u64(0).unwrap();
+        m.stack.push_u64(parent.clone().into()).unwrap();
+        m.execute(SET_CHILD).unwrap();
+        assert_eq!(m.heap.get_child(&parent, 0).unwrap(), None);
+    }
+}
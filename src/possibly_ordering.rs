@@ -13,6 +13,7 @@ use std::cmp::Ordering;
 /// these are used for converting an instance into a byte, or a (valid) byte into an
 /// instance of `PossiblyOrdering`
 #[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum PossiblyOrdering {
     Unordered = 0,
     Less = 1,
@@ -20,6 +21,7 @@ pub enum PossiblyOrdering {
     Greater = 3
 }
 
+#[allow(clippy::wrong_self_convention)]
 impl PossiblyOrdering {
     //noinspection RsSelfConvention
     pub const fn is_eq(self) -> bool {
@@ -50,6 +52,27 @@ impl PossiblyOrdering {
     pub const fn is_ge(self) -> bool {
         !matches!(self, PossiblyOrdering::Less)
     }
+
+    /// Swaps `Less` and `Greater`, for when the compared operands are swapped
+    pub const fn reverse(self) -> Self {
+        match self {
+            PossiblyOrdering::Less => PossiblyOrdering::Greater,
+            PossiblyOrdering::Greater => PossiblyOrdering::Less,
+            ord => ord
+        }
+    }
+
+    /// Selects the greater of `a` and `b`, where `self` is the ordering of `a` relative to `b`\
+    /// `a` is selected when they are `Equal` or `Unordered`, following [is_ge](PossiblyOrdering::is_ge)
+    pub fn max_of<T>(self, a: T, b: T) -> T {
+        if self.is_ge() { a } else { b }
+    }
+
+    /// Selects the lesser of `a` and `b`, where `self` is the ordering of `a` relative to `b`\
+    /// `a` is selected when they are `Equal` or `Unordered`, following [is_le](PossiblyOrdering::is_le)
+    pub fn min_of<T>(self, a: T, b: T) -> T {
+        if self.is_le() { a } else { b }
+    }
 }
 
 impl From<Option<Ordering>> for PossiblyOrdering {
@@ -73,6 +96,17 @@ impl From<Ordering> for PossiblyOrdering {
     }
 }
 
+impl From<PossiblyOrdering> for Option<Ordering> {
+    fn from(ord: PossiblyOrdering) -> Self {
+        match ord {
+            PossiblyOrdering::Unordered => None,
+            PossiblyOrdering::Less => Some(Ordering::Less),
+            PossiblyOrdering::Equal => Some(Ordering::Equal),
+            PossiblyOrdering::Greater => Some(Ordering::Greater)
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct InvalidComparisonByte(pub u8);
 
@@ -89,3 +123,35 @@ impl TryFrom<u8> for PossiblyOrdering {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_of_and_min_of_select_by_ordering() {
+        assert_eq!(PossiblyOrdering::Less.max_of("a", "b"), "b");
+        assert_eq!(PossiblyOrdering::Less.min_of("a", "b"), "a");
+        assert_eq!(PossiblyOrdering::Greater.max_of("a", "b"), "a");
+        assert_eq!(PossiblyOrdering::Greater.min_of("a", "b"), "b");
+        assert_eq!(PossiblyOrdering::Equal.max_of("a", "b"), "a");
+        assert_eq!(PossiblyOrdering::Equal.min_of("a", "b"), "a");
+        assert_eq!(PossiblyOrdering::Unordered.max_of("a", "b"), "a");
+        assert_eq!(PossiblyOrdering::Unordered.min_of("a", "b"), "a");
+    }
+
+    #[test]
+    fn reverse_swaps_less_and_greater() {
+        assert_eq!(PossiblyOrdering::Less.reverse(), PossiblyOrdering::Greater);
+        assert_eq!(PossiblyOrdering::Greater.reverse(), PossiblyOrdering::Less);
+        assert_eq!(PossiblyOrdering::Equal.reverse(), PossiblyOrdering::Equal);
+        assert_eq!(PossiblyOrdering::Unordered.reverse(), PossiblyOrdering::Unordered);
+    }
+
+    #[test]
+    fn round_trips_through_option_ordering() {
+        for ord in [None, Some(Ordering::Less), Some(Ordering::Equal), Some(Ordering::Greater)] {
+            assert_eq!(Option::<Ordering>::from(PossiblyOrdering::from(ord)), ord);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::{Read, Write};
+
+use crate::machine::MachineError;
+
+/// The bytes every program file starts with
+pub const MAGIC: [u8; 4] = *b"BCPG";
+
+/// The length of the header preceding the instructions of a program file
+///
+/// the header is laid out as:\
+/// bytes 0 to 3 hold [MAGIC]\
+/// byte 4 holds the [Version]\
+/// bytes 5 to 7 are reserved, written as 0 and ignored when read\
+/// bytes 8 to 11 hold the little endian CRC-32 (IEEE) of every byte after the header, which is
+/// to say of the instructions, see [verify_checksum]
+pub const HEADER_LEN: usize = 12;
+
+/// The version of the program file format, which changes whenever programs written for one
+/// version may not execute the same on another
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Version(pub u8);
+
+impl Version {
+    /// The version written by [write_header], and the only one [read_and_validate_header] accepts
+    pub const CURRENT: Version = Version(1);
+}
+
+/// Writes the header for `instructions` in a program of the [current](Version::CURRENT) version,
+/// which the instructions are then written after
+pub fn write_header<W: Write>(w: &mut W, instructions: &[u8]) -> io::Result<()> {
+    let mut header = [0; HEADER_LEN];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4] = Version::CURRENT.0;
+    header[8..].copy_from_slice(&crc32(instructions).to_le_bytes());
+    w.write_all(&header)
+}
+
+/// Reads a header written by [write_header], leaving `r` at the start of the instructions
+///
+/// fails with [MachineError::InvalidMagic] if the magic does not match, and with
+/// [MachineError::UnsupportedVersion] for a version other than the current one\
+/// the checksum is not checked, as the instructions have not been read yet, see [verify_checksum]
+pub fn read_and_validate_header<R: Read>(r: &mut R) -> Result<Version, MachineError> {
+    let mut header = [0; HEADER_LEN];
+    r.read_exact(&mut header)?;
+    if header[..4] != MAGIC {
+        return Err(MachineError::InvalidMagic);
+    }
+    let version = Version(header[4]);
+    if version != Version::CURRENT {
+        return Err(MachineError::UnsupportedVersion(version.0));
+    }
+    Ok(version)
+}
+
+/// Checks the checksum in the header of the program file `program` against its instructions,
+/// detecting programs which were truncated or corrupted
+pub fn verify_checksum(program: &[u8]) -> Result<(), IntegrityError> {
+    if program.len() < HEADER_LEN {
+        return Err(IntegrityError::Truncated);
+    }
+    let (header, instructions) = program.split_at(HEADER_LEN);
+    let expected = u32::from_le_bytes(header[8..].try_into().unwrap());
+    let actual = crc32(instructions);
+    if expected != actual {
+        return Err(IntegrityError::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum IntegrityError {
+    /// The program is too short to hold a header
+    Truncated,
+    /// The checksum in the header does not match the one computed over the instructions
+    ChecksumMismatch { expected: u32, actual: u32 }
+}
+
+impl Display for IntegrityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityError::Truncated => write!(f, "program is shorter than its header"),
+            IntegrityError::ChecksumMismatch { expected, actual } => {
+                write!(f, "program checksum {:#010x} does not match the expected {:#010x}", actual, expected)
+            }
+        }
+    }
+}
+
+impl Error for IntegrityError {}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// The CRC-32 (IEEE) of `bytes`, as used by zlib and PNG
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, byte| CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A program file holding `instructions`
+    fn program(instructions: &[u8]) -> Vec<u8> {
+        let mut program = Vec::new();
+        write_header(&mut program, instructions).unwrap();
+        program.extend_from_slice(instructions);
+        program
+    }
+
+    #[test]
+    fn valid_headers_are_read() {
+        let program = program(&[1, 2, 3]);
+        assert_eq!(program.len(), HEADER_LEN + 3);
+        let mut r = &program[..];
+        assert_eq!(read_and_validate_header(&mut r).unwrap(), Version::CURRENT);
+        assert_eq!(r, &[1, 2, 3]);
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut program = program(&[]);
+        program[0] = b'X';
+        assert!(matches!(read_and_validate_header(&mut &program[..]), Err(MachineError::InvalidMagic)));
+    }
+
+    #[test]
+    fn unsupported_versions_are_rejected() {
+        for version in [0, Version::CURRENT.0 + 1, u8::MAX] {
+            let mut program = program(&[]);
+            program[4] = version;
+            assert!(matches!(
+                read_and_validate_header(&mut &program[..]),
+                Err(MachineError::UnsupportedVersion(v)) if v == version
+            ));
+        }
+    }
+
+    #[test]
+    fn matching_checksums_verify() {
+        verify_checksum(&program(&[])).unwrap();
+        verify_checksum(&program(&[0xDE, 0xAD, 0xBE, 0xEF])).unwrap();
+        // the reserved bytes are not covered
+        let mut program = program(&[1, 2, 3]);
+        program[5] = 0xFF;
+        verify_checksum(&program).unwrap();
+    }
+
+    #[test]
+    fn flipped_bits_fail_verification() {
+        let instructions = [3, 1, 0, 0, 0, 0, 0, 0, 0, 4];
+        let program = program(&instructions);
+        for bit in 0..instructions.len() * 8 {
+            let mut corrupted = program.clone();
+            corrupted[HEADER_LEN + bit / 8] ^= 1 << (bit % 8);
+            assert!(matches!(
+                verify_checksum(&corrupted),
+                Err(IntegrityError::ChecksumMismatch { expected, .. }) if expected == crc32(&instructions)
+            ), "bit {}", bit);
+        }
+        let mut corrupted = program.clone();
+        corrupted[HEADER_LEN - 1] ^= 0x80;
+        assert!(matches!(verify_checksum(&corrupted), Err(IntegrityError::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn truncated_programs_fail_verification() {
+        let program = program(&[1, 2, 3]);
+        for len in 0..HEADER_LEN {
+            assert!(matches!(verify_checksum(&program[..len]), Err(IntegrityError::Truncated)), "length {}", len);
+        }
+        for len in HEADER_LEN..program.len() {
+            assert!(matches!(verify_checksum(&program[..len]), Err(IntegrityError::ChecksumMismatch { .. })), "length {}", len);
+        }
+    }
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+}
@@ -0,0 +1,141 @@
+use std::io;
+use std::io::Write;
+use std::time::Instant;
+
+use crate::compute_stack::ComputeStack;
+use crate::machine::{HostReturn, Machine};
+use crate::ReadSeek;
+
+/// Host function ids from here on are reserved for the standard library, see [register_stdlib]
+pub const STDLIB_BASE: u64 = 0xFFFF_FFFF_FFFF_FF00;
+
+/// Pops 8 bytes as **`value`**\
+/// Prints **`value`** as an unsigned integer on its own line
+pub const PRINT_U64: u64 = STDLIB_BASE;
+
+/// Pushes 8 bytes corresponding to the nanoseconds elapsed on a monotonic clock since the standard
+/// library was registered, saturating at `u64::MAX`
+pub const CLOCK_NANOS: u64 = STDLIB_BASE + 1;
+
+/// Pushes 8 bytes corresponding to the next value of a pseudorandom number generator, which
+/// produces the same values for the same seed
+pub const RANDOM_U64: u64 = STDLIB_BASE + 2;
+
+/// The seed used by [register_stdlib], so programs are deterministic unless seeded otherwise
+pub const DEFAULT_SEED: u64 = 0x853C_49E6_748F_EA9B;
+
+/// Registers the standard library host functions on `machine`, called through
+/// [`CALL_EXT`](crate::instruction::Instruction::CALL_EXT) with the ids in this module, printing
+/// to stdout and seeding the generator with [DEFAULT_SEED]
+pub fn register_stdlib<R: ReadSeek, S: ComputeStack>(machine: &mut Machine<R, S>) {
+    register_stdlib_with(machine, io::stdout(), DEFAULT_SEED);
+}
+
+/// Registers the standard library host functions on `machine` as [register_stdlib] does, printing
+/// to `output` and seeding the generator with `seed`
+pub fn register_stdlib_with<R, S, W>(machine: &mut Machine<R, S>, mut output: W, seed: u64)
+where R: ReadSeek, S: ComputeStack, W: Write + 'static {
+    let start = Instant::now();
+    let mut state = seed;
+    machine
+        .register_host_fn(PRINT_U64, move |stack, _| {
+            writeln!(output, "{}", stack.pop_u64()?)?;
+            Ok(HostReturn::None)
+        })
+        .register_host_fn(CLOCK_NANOS, move |_, _| {
+            let nanos = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+            Ok(HostReturn::Bytes(nanos.to_le_bytes().to_vec()))
+        })
+        .register_host_fn(RANDOM_U64, move |_, _| {
+            Ok(HostReturn::Bytes(split_mix_64(&mut state).to_le_bytes().to_vec()))
+        });
+}
+
+/// Advances `state` and returns the next value of the SplitMix64 generator, which accepts any seed
+fn split_mix_64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::InstructionReceiver;
+    use crate::instruction::Instruction::*;
+
+    /// Output shared with the test after the machine takes ownership of it
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run(seed: u64, program: impl FnOnce(&mut Vec<u8>)) -> (Machine, SharedOutput) {
+        let mut bytecode = Vec::new();
+        program(&mut bytecode);
+        let mut machine = Machine::new(Box::new(Cursor::new(bytecode)));
+        let output = SharedOutput::default();
+        register_stdlib_with(&mut machine, output.clone(), seed);
+        machine.run().unwrap();
+        (machine, output)
+    }
+
+    #[test]
+    fn prints_u64s() {
+        let (machine, output) = run(DEFAULT_SEED, |p| {
+            p.instruct(PSH_8(42u64.to_le_bytes()))
+                .instruct(PSH_8(PRINT_U64.to_le_bytes()))
+                .instruct(CALL_EXT)
+                .instruct(PSH_8(u64::MAX.to_le_bytes()))
+                .instruct(PSH_8(PRINT_U64.to_le_bytes()))
+                .instruct(CALL_EXT);
+        });
+        assert_eq!(*output.0.borrow(), format!("42\n{}\n", u64::MAX).into_bytes());
+        assert_eq!(machine.stack().size(), 0);
+    }
+
+    #[test]
+    fn the_clock_never_goes_backwards() {
+        let (mut machine, _) = run(DEFAULT_SEED, |p| {
+            p.instruct(PSH_8(CLOCK_NANOS.to_le_bytes()))
+                .instruct(CALL_EXT)
+                .instruct(PSH_8(CLOCK_NANOS.to_le_bytes()))
+                .instruct(CALL_EXT);
+        });
+        let later = machine.stack.pop_u64().unwrap();
+        let earlier = machine.stack.pop_u64().unwrap();
+        assert!(later >= earlier);
+    }
+
+    #[test]
+    fn random_values_follow_the_seed() {
+        let random = |seed| {
+            let (mut machine, _) = run(seed, |p| {
+                for _ in 0..3 {
+                    p.instruct(PSH_8(RANDOM_U64.to_le_bytes())).instruct(CALL_EXT);
+                }
+            });
+            assert_eq!(machine.stack().size(), 24);
+            machine.stack.pop_n_u64(3).unwrap()
+        };
+        let values = random(DEFAULT_SEED);
+        assert_eq!(values, random(DEFAULT_SEED));
+        assert_ne!(values[0], values[1]);
+        assert_ne!(values, random(1));
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io::Cursor;
+
+use crate::Instruction;
+use crate::instruction::decode_program;
+use crate::machine::MachineError;
+
+/// Checks that every statically known jump target lands on an instruction boundary, or on the end
+/// of the program
+///
+/// a target is statically known when it is pushed by a `PSH_8` immediately preceding a `JSR` or
+/// `CALL_FRAME`, or immediately preceding a `PSH_1` which itself immediately precedes a `JMP_*`
+/// other than `JMP_IMM`, and the address of every `JMP_IMM` is statically known
+pub fn validate_jump_targets(program: &[u8]) -> Result<(), ValidationError> {
+    let decoded = decode_program(&mut Cursor::new(program)).map_err(ValidationError::Decode)?;
+    let mut boundaries: HashSet<u64> = decoded.iter().map(|(offset, _)| *offset).collect();
+    boundaries.insert(program.len() as u64);
+    for (index, (offset, instruction)) in decoded.iter().enumerate() {
+        if let Instruction::JMP_IMM(address) = instruction {
+            let target = u64::from_le_bytes(*address);
+            if !boundaries.contains(&target) {
+                return Err(ValidationError::InvalidJumpTarget(*offset, target));
+            }
+            continue;
+        }
+        let address_index = match instruction {
+            Instruction::JSR | Instruction::CALL_FRAME => index.checked_sub(1),
+            Instruction::JMP_EQ | Instruction::JMP_NE | Instruction::JMP_GE |
+            Instruction::JMP_GT | Instruction::JMP_LE | Instruction::JMP_LT |
+            Instruction::JMP_IF | Instruction::JMP_IF_NOT => {
+                match index.checked_sub(1).map(|i| &decoded[i].1) {
+                    Some(Instruction::PSH_1(_)) => index.checked_sub(2),
+                    _ => None
+                }
+            }
+            _ => None
+        };
+        if let Some((_, Instruction::PSH_8(address))) = address_index.map(|i| &decoded[i]) {
+            let target = u64::from_le_bytes(*address);
+            if !boundaries.contains(&target) {
+                return Err(ValidationError::InvalidJumpTarget(*offset, target));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decodes the whole program, reporting every opcode which does not name an instruction rather
+/// than only the first, along with an instruction cut short by the end of the program
+///
+/// decoding resumes at the byte after an unknown opcode
+pub fn verify(program: &[u8]) -> Result<(), VerifyError> {
+    let mut reader = Cursor::new(program);
+    let mut problems = Vec::new();
+    loop {
+        match Instruction::from_reader(&mut reader) {
+            Ok(_) => {}
+            Err(MachineError::EndOfInstructions) => break,
+            Err(e @ MachineError::UnknownInstruction { .. }) => problems.push(e),
+            Err(e) => {
+                problems.push(e);
+                break;
+            }
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(VerifyError { problems })
+    }
+}
+
+/// The bytes `instruction` pops from the stack at the least, and the net change in the stack's
+/// size after executing it, or [None] for `CALL_EXT`, `CALL_EXT_N` and `MOV_ST_HP_N`, whose effect
+/// depends on values only known at runtime
+///
+/// the stack as a whole is modelled, so the separate call stack of `CALL_FRAME` and `RET_FRAME`
+/// is not counted
+pub fn stack_effect(instruction: &Instruction) -> Option<(usize, isize)> {
+    use Instruction::*;
+    let (pops, pushes): (usize, usize) = match instruction {
+        PSH_1(_) => (0, 1),
+        PSH_2(_) => (0, 2),
+        PSH_4(_) => (0, 4),
+        PSH_8(_) => (0, 8),
+        PSH_NULL => (0, 8),
+        POP_1 => (1, 0),
+        POP_2 => (2, 0),
+        POP_4 => (4, 0),
+        POP_8 => (8, 0),
+
+        ALLOC => (16, 8),
+        COPY_REF => (8, 16),
+        SET_CHILD => (24, 0),
+        GET_CHILD => (16, 8),
+        MOV_ST_HP_1 => (17, 0),
+        MOV_ST_HP_2 => (18, 0),
+        MOV_ST_HP_4 => (20, 0),
+        MOV_ST_HP_8 => (24, 0),
+        MOV_HP_ST_1 => (16, 1),
+        MOV_HP_ST_2 => (16, 2),
+        MOV_HP_ST_4 => (16, 4),
+        MOV_HP_ST_8 => (16, 8),
+
+        JSR => (8, 8),
+        RET => (8, 0),
+        JMP_EQ | JMP_NE | JMP_GE | JMP_GT | JMP_LE | JMP_LT => (9, 0),
+
+        CMP_U_1 | CMP_S_1 => (2, 1),
+        CMP_U_2 | CMP_S_2 => (4, 1),
+        CMP_U_4 | CMP_S_4 | CMP_F4 => (8, 1),
+        CMP_U_8 | CMP_S_8 | CMP_F8 => (16, 1),
+
+        NOT_1 | CLZ_1 | CTZ_1 | POPCNT_1 => (1, 1),
+        NOT_2 | CLZ_2 | CTZ_2 | POPCNT_2 => (2, 2),
+        NOT_4 | CLZ_4 | CTZ_4 | POPCNT_4 | NEG_F_4 | ABS_F_4 | SQRT_F_4 | SIN_F_4 | COS_F_4 => (4, 4),
+        NOT_8 | CLZ_8 | CTZ_8 | POPCNT_8 | NEG_F_8 | ABS_F_8 | SQRT_F_8 | SIN_F_8 | COS_F_8 => (8, 8),
+
+        AND_1 | OR_1 | XOR_1 | ADD_1 | SUB_1 | MUL_1 |
+        MIN_U_1 | MAX_U_1 | MIN_S_1 | MAX_S_1 => (2, 1),
+        AND_2 | OR_2 | XOR_2 | ADD_2 | SUB_2 | MUL_2 |
+        MIN_U_2 | MAX_U_2 | MIN_S_2 | MAX_S_2 => (4, 2),
+        AND_4 | OR_4 | XOR_4 | ADD_4 | SUB_4 | MUL_4 |
+        MIN_U_4 | MAX_U_4 | MIN_S_4 | MAX_S_4 |
+        ADD_F_4 | SUB_F_4 | MUL_F_4 | DIV_F_4 | REM_F_4 => (8, 4),
+        AND_8 | OR_8 | XOR_8 | ADD_8 | SUB_8 | MUL_8 |
+        MIN_U_8 | MAX_U_8 | MIN_S_8 | MAX_S_8 |
+        ADD_F_8 | SUB_F_8 | MUL_F_8 | DIV_F_8 | REM_F_8 => (16, 8),
+
+        SHL_1 | SHR_1 | SAR_1 | ROL_1 | ROR_1 => (2, 1),
+        SHL_2 | SHR_2 | SAR_2 | ROL_2 | ROR_2 => (3, 2),
+        SHL_4 | SHR_4 | SAR_4 | ROL_4 | ROR_4 => (5, 4),
+        SHL_8 | SHR_8 | SAR_8 | ROL_8 | ROR_8 => (9, 8),
+
+        DIV_REM_U_1 | DIV_REM_S_1 => (2, 2),
+        DIV_REM_U_2 | DIV_REM_S_2 => (4, 4),
+        DIV_REM_U_4 | DIV_REM_S_4 => (8, 8),
+        DIV_REM_U_8 | DIV_REM_S_8 => (16, 16),
+
+        FMA_F_4 => (12, 4),
+        FMA_F_8 => (24, 8),
+
+        CNV_U8_F4 | CNV_S8_F4 | CNV_F8_F4 => (8, 4),
+        CNV_U8_F8 | CNV_S8_F8 | CNV_F8_U8 | CNV_F8_S8 => (8, 8),
+        CNV_F4_U8 | CNV_F4_S8 | CNV_F4_F8 | CNV_U4_U8 | CNV_S4_S8 => (4, 8),
+        CNV_U1_U8 | CNV_S1_S8 => (1, 8),
+        CNV_U2_U8 | CNV_S2_S8 => (2, 8),
+        CNV_U8_U1 => (8, 1),
+        CNV_U8_U2 => (8, 2),
+        CNV_U8_U4 => (8, 4),
+
+        IS_NAN_4 | IS_INF_4 => (4, 1),
+        IS_NAN_8 | IS_INF_8 => (8, 1),
+
+        CALL_EXT | CALL_EXT_N | MOV_ST_HP_N => return None,
+
+        DUP_1 => (1, 2),
+        DUP_2 => (2, 4),
+        DUP_4 => (4, 8),
+        DUP_8 => (8, 16),
+        SWAP_1 => (2, 2),
+        SWAP_2 => (4, 4),
+        SWAP_4 => (8, 8),
+        SWAP_8 => (16, 16),
+
+        FREE => (8, 0),
+        GC => (0, 8),
+
+        LOAD_8 => (8, 8),
+        STORE_8 => (16, 0),
+        CALL_FRAME => (8, 0),
+        RET_FRAME | HALT | JMP_IMM(_) => (0, 0),
+        JMP_IF | JMP_IF_NOT => (9, 0),
+
+        CMP_REF => (16, 1),
+        IS_NULL => (8, 1),
+        OBJ_CHILDREN_LEN | OBJ_DATA_LEN => (8, 8),
+        MOV_HP_HP => (40, 0),
+        FILL => (25, 0)
+    };
+    Some((pops, pushes as isize - pops as isize))
+}
+
+/// Models the stack through the straight-line code at the start of the program, failing if an
+/// instruction there would pop more bytes than the instructions before it left on the stack
+///
+/// the analysis conservatively stops at the first instruction which may transfer control, or
+/// whose [stack_effect] is not known
+pub fn analyze_stack_balance(program: &[u8]) -> Result<(), StackBalanceError> {
+    let decoded = decode_program(&mut Cursor::new(program)).map_err(StackBalanceError::Decode)?;
+    let mut height = 0usize;
+    for (offset, instruction) in decoded {
+        let Some((pops, delta)) = stack_effect(&instruction) else {
+            break;
+        };
+        if pops > height {
+            return Err(StackBalanceError::Underflow(offset));
+        }
+        if instruction.is_branch() {
+            break;
+        }
+        height = height.checked_add_signed(delta).ok_or(StackBalanceError::Overflow(offset))?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum StackBalanceError {
+    /// The program could not be decoded
+    Decode(MachineError),
+    /// The instruction at the offset would pop more bytes than are on the stack
+    Underflow(u64),
+    /// The instruction at the offset would grow the modelled stack past `usize::MAX` bytes
+    Overflow(u64)
+}
+
+impl Display for StackBalanceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StackBalanceError::Decode(e) => write!(f, "could not decode the program: {}", e),
+            StackBalanceError::Underflow(offset) => {
+                write!(f, "instruction at offset {} pops more bytes than are on the stack", offset)
+            }
+            StackBalanceError::Overflow(offset) => {
+                write!(f, "instruction at offset {} overflows the modelled stack size", offset)
+            }
+        }
+    }
+}
+
+impl Error for StackBalanceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StackBalanceError::Decode(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ValidationError {
+    /// The program could not be decoded
+    Decode(MachineError),
+    /// The jump at the first offset targets the second offset, which is not an instruction boundary
+    InvalidJumpTarget(u64, u64)
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Decode(e) => write!(f, "could not decode the program: {}", e),
+            ValidationError::InvalidJumpTarget(offset, target) => {
+                write!(f, "jump at offset {} targets offset {}, which is not an instruction boundary", offset, target)
+            }
+        }
+    }
+}
+
+impl Error for ValidationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ValidationError::Decode(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+/// The problems found by [verify], in the order they occur in the program, each being a
+/// [`UnknownInstruction`](MachineError::UnknownInstruction) or, only as the last problem, an
+/// [`IncompleteInstruction`](MachineError::IncompleteInstruction)
+#[derive(Debug)]
+pub struct VerifyError {
+    pub problems: Vec<MachineError>
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "found {} problem(s) decoding the program", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for VerifyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn accepts_a_valid_loop() {
+        let program = assemble("
+        loop:
+            PSH_8 1
+            POP_8
+            PSH_8 loop
+            PSH_1 0
+            JMP_NE
+            PSH_8 end
+            JSR
+        end:
+        ").unwrap();
+        assert!(validate_jump_targets(&program).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_jump_into_an_immediate() {
+        let program = assemble("
+            PSH_8 1         ; the first byte of this push's immediate
+            PSH_1 0
+            JMP_NE
+        ").unwrap();
+        let jump_offset = (Instruction::PSH_8([0; 8]).encoded_len() + Instruction::PSH_1([0]).encoded_len()) as u64;
+        assert!(matches!(
+            validate_jump_targets(&program),
+            Err(ValidationError::InvalidJumpTarget(offset, 1)) if offset == jump_offset
+        ));
+    }
+
+    #[test]
+    fn errors_display_their_offsets() {
+        let error = ValidationError::InvalidJumpTarget(11, 1);
+        assert_eq!(error.to_string(), "jump at offset 11 targets offset 1, which is not an instruction boundary");
+        assert!(error.source().is_none());
+    }
+
+    #[test]
+    fn checks_immediate_jump_targets() {
+        assert!(validate_jump_targets(&assemble("JMP_IMM end\nend:").unwrap()).is_ok());
+        assert!(matches!(
+            validate_jump_targets(&assemble("JMP_IMM 1").unwrap()),
+            Err(ValidationError::InvalidJumpTarget(0, 1))
+        ));
+    }
+
+    #[test]
+    fn verifies_a_clean_program() {
+        assert!(verify(&assemble("PSH_4 7\nPOP_4\nHALT").unwrap()).is_ok());
+        assert!(verify(&[]).is_ok());
+    }
+
+    #[test]
+    fn verify_reports_every_unknown_opcode() {
+        let mut program = vec![250];
+        program.extend(assemble("PSH_1 1").unwrap());
+        program.push(u8::MAX);
+        let error = verify(&program).unwrap_err();
+        assert!(matches!(
+            error.problems[..],
+            [
+                MachineError::UnknownInstruction { opcode: 250, offset: 0 },
+                MachineError::UnknownInstruction { opcode: u8::MAX, offset: 3 }
+            ]
+        ));
+    }
+
+    #[test]
+    fn verify_reports_a_truncated_final_instruction() {
+        let mut program = assemble("POP_1\nPSH_4 7").unwrap();
+        program.pop();
+        let opcode = Instruction::PSH_4([0; 4]).opcode();
+        let error = verify(&program).unwrap_err();
+        assert!(matches!(
+            error.problems[..],
+            [MachineError::IncompleteInstruction { opcode: o, offset: 1 }] if o == opcode
+        ));
+    }
+
+    #[test]
+    fn verify_errors_display_every_problem() {
+        let error = verify(&[250, 255]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "found 2 problem(s) decoding the program\n\
+            unknown instruction opcode 250 at offset 0x0\n\
+            unknown instruction opcode 255 at offset 0x1"
+        );
+    }
+
+    #[test]
+    fn accepts_balanced_straight_line_code() {
+        let program = assemble("
+            PSH_8 1
+            PSH_8 2
+            ADD_8
+            DUP_8
+            MUL_8
+            POP_8
+        ").unwrap();
+        assert!(analyze_stack_balance(&program).is_ok());
+    }
+
+    #[test]
+    fn rejects_an_underflow() {
+        let program = assemble("
+            PSH_4 1
+            PSH_2 2
+            ADD_4
+        ").unwrap();
+        let add_offset = (Instruction::PSH_4([0; 4]).encoded_len() + Instruction::PSH_2([0; 2]).encoded_len()) as u64;
+        assert!(matches!(
+            analyze_stack_balance(&program),
+            Err(StackBalanceError::Underflow(offset)) if offset == add_offset
+        ));
+    }
+
+    #[test]
+    fn stops_stack_analysis_at_branches() {
+        // the POP_8 may be reached with a different stack through a jump, so it is not checked
+        let program = assemble("
+            PSH_8 end
+            JSR
+        end:
+            POP_8
+            POP_8
+        ").unwrap();
+        assert!(analyze_stack_balance(&program).is_ok());
+        assert_eq!(stack_effect(&Instruction::ADD_8), Some((16, -8)));
+        assert_eq!(stack_effect(&Instruction::PSH_8([0; 8])), Some((0, 8)));
+        assert_eq!(stack_effect(&Instruction::CALL_EXT), None);
+    }
+
+    #[test]
+    fn stack_balance_errors_display_their_offsets() {
+        let error = StackBalanceError::Underflow(3);
+        assert_eq!(error.to_string(), "instruction at offset 3 pops more bytes than are on the stack");
+        assert!(error.source().is_none());
+        let error = analyze_stack_balance(&[255]).unwrap_err();
+        assert!(matches!(error, StackBalanceError::Decode(MachineError::UnknownInstruction { .. })));
+        assert!(error.source().is_some());
+    }
+}
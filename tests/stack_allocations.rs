@@ -0,0 +1,87 @@
+//! Benchmarks the hot stack paths while counting heap allocations, kept in its own test binary
+//! as the counting allocator is global
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::time::Instant;
+
+use bytecode_playground::compute_stack::ComputeStack;
+use bytecode_playground::instruction::Instruction::*;
+use bytecode_playground::machine::Machine;
+
+struct CountingAllocator;
+
+thread_local! {
+    /// allocations made by each thread, so tests running in parallel do not count each other's
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count_allocation() {
+    // the thread local is gone while the thread is being torn down
+    let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const ITERATIONS: u64 = 100_000;
+
+/// Runs `f` [ITERATIONS] times, returning how many allocations it made
+fn allocations(name: &str, mut f: impl FnMut(u64)) -> usize {
+    let start = Instant::now();
+    let before = ALLOCATIONS.with(Cell::get);
+    for i in 0..ITERATIONS {
+        f(i);
+    }
+    let allocations = ALLOCATIONS.with(Cell::get) - before;
+    println!("{}: {:?} per iteration", name, start.elapsed() / ITERATIONS as u32);
+    allocations
+}
+
+#[test]
+fn typed_pops_do_not_allocate() {
+    let mut stack: Box<dyn ComputeStack> = Box::new(Vec::new());
+    stack.reserve(32);
+    let allocations = allocations("push and pop", |i| {
+        stack.push_u64(i).unwrap();
+        stack.push_u32(i as u32).unwrap();
+        stack.push_f64(i as f64).unwrap();
+        assert_eq!(stack.pop_f64().unwrap(), i as f64);
+        assert_eq!(stack.pop_u32().unwrap(), i as u32);
+        assert_eq!(stack.pop_u64().unwrap(), i);
+    });
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn arithmetic_does_not_allocate() {
+    let mut machine = Machine::from_bytecode(Vec::new());
+    machine.reserve_stack(32);
+    let allocations = allocations("arithmetic", |i| {
+        machine.execute(PSH_8(i.to_le_bytes())).unwrap();
+        machine.execute(DUP_8).unwrap();
+        machine.execute(MUL_8).unwrap();
+        machine.execute(PSH_8(1u64.to_le_bytes())).unwrap();
+        machine.execute(SWAP_8).unwrap();
+        machine.execute(ADD_8).unwrap();
+        machine.execute(POP_8).unwrap();
+    });
+    assert_eq!(allocations, 0);
+    assert_eq!(machine.stack().size(), 0);
+}